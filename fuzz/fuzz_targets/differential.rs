@@ -0,0 +1,19 @@
+// Compile random programs and compare them against the evaluator. The bytes
+// left over by the program generator become the program's input.
+#![no_main]
+
+use bf_compiler::compiler::differential;
+use bf_compiler::random::{random_program, Entropy};
+use libfuzzer_sys::fuzz_target;
+
+const MAX_INPUT: usize = 16;
+const MAX_STEPS: u64 = 10_000_000;
+
+fuzz_target!(|data: &[u8]| {
+    let mut entropy = Entropy::new(data);
+    let program = random_program(&mut entropy);
+    let input = &entropy.rest()[..MAX_INPUT.min(entropy.rest().len())];
+    let differences = differential(&program, input, MAX_STEPS)
+        .unwrap_or_else(|err| panic!("{}\n{}", err, program));
+    assert!(differences.is_empty(), "{:?}\n{}", differences, program);
+});
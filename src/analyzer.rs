@@ -0,0 +1,252 @@
+// Head movement analysis.
+//
+// Offsets are measured in frames relative to the starting frame, negative
+// offsets are on the left. A side is marked unbounded once a loop keeps
+// pushing it further on every iteration; the offset of that side is then the
+// furthest one actually observed before giving up.
+
+use crate::parser::{Direction, Statement, AST};
+use crate::scanner::Location;
+use std::fmt::Display;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Bounds {
+    pub min: i64,
+    pub max: i64,
+    pub min_unbounded: bool,
+    pub max_unbounded: bool,
+}
+
+impl Bounds {
+    fn origin() -> Self {
+        Self {
+            min: 0,
+            max: 0,
+            min_unbounded: false,
+            max_unbounded: false,
+        }
+    }
+    fn shift(self, offset: i64) -> Self {
+        Self {
+            min: self.min + offset,
+            max: self.max + offset,
+            ..self
+        }
+    }
+    fn join(self, other: Self) -> Self {
+        Self {
+            min: self.min.min(other.min),
+            max: self.max.max(other.max),
+            min_unbounded: self.min_unbounded || other.min_unbounded,
+            max_unbounded: self.max_unbounded || other.max_unbounded,
+        }
+    }
+    // Mark every side that grew since `self` as unbounded. The finite offsets
+    // of a side are frozen as soon as it is unbounded so the iteration over a
+    // loop body always terminates.
+    fn widen(self, other: Self) -> Self {
+        let mut widened = self;
+        if other.min < self.min || other.min_unbounded {
+            widened.min_unbounded = true;
+        }
+        if other.max > self.max || other.max_unbounded {
+            widened.max_unbounded = true;
+        }
+        widened
+    }
+}
+
+impl Display for Bounds {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let side = |offset: i64, unbounded: bool| {
+            if unbounded {
+                format!("{} or further", offset)
+            } else {
+                offset.to_string()
+            }
+        };
+        write!(
+            f,
+            "{} ..= {}",
+            side(self.min, self.min_unbounded),
+            side(self.max, self.max_unbounded)
+        )
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Diagnostic {
+    pub location: Option<Location>,
+    pub message: String,
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.location {
+            Some(location) => write!(f, "warning: {}: {}", location, self.message),
+            None => write!(f, "warning: {}", self.message),
+        }
+    }
+}
+
+fn walk(statements: &[Statement], mut position: Bounds, reach: &mut Bounds) -> Bounds {
+    for stmt in statements {
+        position = match stmt {
+            Statement::Move(Direction::Right, _) => position.shift(1),
+            Statement::Move(Direction::Left, _) => position.shift(-1),
+            Statement::IF(_, if_func, else_func) => {
+                let after_if = walk(if_func.statements(), position, reach);
+                let after_else = match else_func {
+                    Some(else_func) => walk(else_func.statements(), position, reach),
+                    None => position,
+                };
+                after_if.join(after_else)
+            }
+            Statement::WHILE(_, func) => loop {
+                let after = walk(func.statements(), position, reach).join(position);
+                let widened = position.widen(after);
+                if widened == position {
+                    break position;
+                }
+                position = widened;
+            },
+            _ => position,
+        };
+        *reach = reach.join(position);
+    }
+    position
+}
+
+// Frames reachable by the head while running the program.
+pub fn reach(ast: &AST) -> Bounds {
+    let mut reach = Bounds::origin();
+    walk(ast.statements(), Bounds::origin(), &mut reach);
+    reach
+}
+
+fn net_movement_message(net: Bounds) -> Option<String> {
+    if net == Bounds::origin() {
+        None
+    } else if net.min == net.max && !net.min_unbounded && !net.max_unbounded {
+        Some(format!(
+            "loop moves the head {} frame(s) per iteration",
+            net.min
+        ))
+    } else {
+        Some(format!(
+            "loop moves the head by a varying number of frames per iteration ({})",
+            net
+        ))
+    }
+}
+
+fn check_loops(statements: &[Statement], source: &str, diagnostics: &mut Vec<Diagnostic>) {
+    for stmt in statements {
+        match stmt {
+            Statement::WHILE(cond, func) => {
+                let net = walk(func.statements(), Bounds::origin(), &mut Bounds::origin());
+                if let Some(message) = net_movement_message(net) {
+                    let location = cond
+                        .compares()
+                        .first()
+                        .and_then(|c| Location::find(source, c.variable()));
+                    diagnostics.push(Diagnostic { location, message });
+                }
+                check_loops(func.statements(), source, diagnostics);
+            }
+            Statement::IF(_, if_func, else_func) => {
+                check_loops(if_func.statements(), source, diagnostics);
+                if let Some(else_func) = else_func {
+                    check_loops(else_func.statements(), source, diagnostics);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+// Flag every loop whose body does not bring the head back to the frame it
+// started the iteration in. `source` is the text `ast` was parsed from.
+pub fn check_movement(ast: &AST, source: &str) -> Vec<Diagnostic> {
+    let mut diagnostics = vec![];
+    check_loops(ast.statements(), source, &mut diagnostics);
+    diagnostics
+}
+
+// Number of frames the program moves left of its starting frame, and whether
+// that number is only a lower bound.
+pub fn left_excursion(ast: &AST) -> (usize, bool) {
+    let reach = reach(ast);
+    ((-reach.min).max(0) as usize, reach.min_unbounded)
+}
+
+#[cfg(test)]
+#[allow(clippy::module_inception)]
+mod analyzer {
+    use super::*;
+    use crate::scanner::TokenStream;
+    fn reach_of(program: &str) -> Bounds {
+        let tokens = TokenStream::try_from(program).unwrap();
+        let ast = AST::try_from(&*tokens.into_tokens()).unwrap();
+        reach(&ast)
+    }
+    fn bounds(min: i64, max: i64, min_unbounded: bool, max_unbounded: bool) -> Bounds {
+        Bounds {
+            min,
+            max,
+            min_unbounded,
+            max_unbounded,
+        }
+    }
+    #[test]
+    fn test_straight_line() {
+        let program = "move_left move_left move_right move_right move_right";
+        assert_eq!(reach_of(program), bounds(-2, 1, false, false));
+    }
+    #[test]
+    fn test_if_else() {
+        let program = "if a == 1 { move_left } else { move_right move_right } move_left";
+        assert_eq!(reach_of(program), bounds(-2, 2, false, false));
+    }
+    #[test]
+    fn test_balanced_loop() {
+        let program = "while a != 0 { move_left input ( a ) move_right }";
+        assert_eq!(reach_of(program), bounds(-1, 0, false, false));
+    }
+    #[test]
+    fn test_unbounded_loop() {
+        let program = "move_left while a != 0 { move_left input ( a ) }";
+        assert_eq!(reach_of(program), bounds(-2, 0, true, false));
+    }
+    #[test]
+    fn test_conditional_move_in_loop() {
+        let program = "while a != 0 { if a == 1 { move_right } input ( a ) }";
+        assert_eq!(reach_of(program), bounds(0, 1, false, true));
+    }
+    #[test]
+    fn test_check_movement() {
+        let program = "while a != 0 {\n  move_left\n  while b != 0 { move_left move_right }\n}\nwhile c != 0 { if c == 1 { move_right } }";
+        let tokens = TokenStream::try_from(program).unwrap();
+        let ast = AST::try_from(&*tokens.into_tokens()).unwrap();
+        let diagnostics = check_movement(&ast, program);
+        let expect = vec![
+            Diagnostic {
+                location: Some(Location { line: 1, column: 7 }),
+                message: "loop moves the head -1 frame(s) per iteration".to_string(),
+            },
+            Diagnostic {
+                location: Some(Location { line: 5, column: 7 }),
+                message:
+                    "loop moves the head by a varying number of frames per iteration (0 ..= 1)"
+                        .to_string(),
+            },
+        ];
+        assert_eq!(diagnostics, expect);
+    }
+    #[test]
+    fn test_left_excursion() {
+        let tokens = TokenStream::try_from("move_left move_left move_right").unwrap();
+        let ast = AST::try_from(&*tokens.into_tokens()).unwrap();
+        assert_eq!(left_excursion(&ast), (2, false));
+    }
+}
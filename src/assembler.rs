@@ -1,4 +1,4 @@
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use std::{collections::HashMap, fmt::Display};
 
 // TODO: The Variable might be able to hold &str instead of String
@@ -41,15 +41,24 @@ impl Value {
     }
 }
 
+// The `index`-th part of an instruction as a number.
+fn operand(parts: &[&str], index: usize) -> Result<usize> {
+    let part = parts
+        .get(index)
+        .ok_or_else(|| anyhow!("Missing operand of {}", parts[0]))?;
+    part.parse::<usize>()
+        .map_err(|_| anyhow!("Expected a number, found {}", part))
+}
+
 fn parse_copy(parts: &[&str]) -> Result<String> {
-    let src = parts[0].parse::<usize>()?;
+    let src = operand(parts, 1)?;
     let mut inner = String::new();
-    for part in &parts[1..] {
+    for part in &parts[2..] {
         let dest = part.parse::<usize>()?;
         inner.push_str(&format!("{}+{}", ">".repeat(dest), "<".repeat(dest)));
     }
     let mut clear = String::new();
-    for part in &parts[1..] {
+    for part in &parts[2..] {
         let dest = part.parse::<usize>()?;
         clear.push_str(&format!("{}[-]{}", ">".repeat(dest), "<".repeat(dest)));
     }
@@ -75,6 +84,103 @@ fn replace(parts: &[&str], map: &HashMap<&str, usize>) -> String {
         .join(" ")
 }
 
+// Relative pointer movement from cell `from` to cell `to`.
+fn shift(from: usize, to: usize) -> String {
+    if to > from {
+        ">".repeat(to - from)
+    } else {
+        "<".repeat(from - to)
+    }
+}
+
+// Shortest run of `+` or `-` adding `diff` to a cell.
+fn adjust(diff: u8) -> String {
+    if diff <= 128 {
+        "+".repeat(diff as usize)
+    } else {
+        "-".repeat(256 - diff as usize)
+    }
+}
+
+fn adjust_len(diff: u8) -> usize {
+    (diff as usize).min(256 - diff as usize)
+}
+
+// Shortest code setting `var` to `val`, either by adjusting it directly or by
+// adding `count * step` with a multiplication loop on the zero `scratch` cell
+// and then adjusting the remainder. A known `current` value is adjusted from
+// instead of being cleared.
+fn load_constant(var: usize, val: u8, current: Option<u8>, scratch: Option<usize>) -> String {
+    let (clear, diff) = match current {
+        Some(current) if current == val => return String::new(),
+        Some(current) => ("", val.wrapping_sub(current)),
+        None => ("[-]", val),
+    };
+    let direct = format!(
+        "{}{}{}{}",
+        shift(0, var),
+        clear,
+        adjust(diff),
+        shift(var, 0)
+    );
+    let Some(scratch) = scratch.filter(|s| *s != var) else {
+        return direct;
+    };
+    let distance = scratch.abs_diff(var);
+    let mut best: Option<(usize, usize, usize, bool)> = None;
+    for count in 2..=16usize {
+        for step in 2..=128usize {
+            for negative in [false, true] {
+                let product = (count * step % 256) as u8;
+                let product = if negative {
+                    product.wrapping_neg()
+                } else {
+                    product
+                };
+                let len = count + step + 4 * distance + 3 + adjust_len(diff.wrapping_sub(product));
+                if best.is_none_or(|(best_len, ..)| len < best_len) {
+                    best = Some((len, count, step, negative));
+                }
+            }
+        }
+    }
+    let Some((len, count, step, negative)) = best else {
+        return direct;
+    };
+    if 2 * var + clear.len() + len >= direct.len() {
+        return direct;
+    }
+    let product = (count * step % 256) as u8;
+    let product = if negative {
+        product.wrapping_neg()
+    } else {
+        product
+    };
+    let rest = diff.wrapping_sub(product);
+    let back = if rest == 0 {
+        shift(scratch, 0)
+    } else {
+        format!("{}{}{}", shift(scratch, var), adjust(rest), shift(var, 0))
+    };
+    let multiply = format!(
+        "{}{}{}{}[{}{}{}-]{}",
+        shift(0, var),
+        clear,
+        shift(var, scratch),
+        "+".repeat(count),
+        shift(scratch, var),
+        if negative { "-" } else { "+" }.repeat(step),
+        shift(var, scratch),
+        back,
+    );
+    if multiply.len() < direct.len() {
+        multiply
+    } else {
+        direct
+    }
+}
+
+// Defines are replaced by empty lines so that lines keep their numbers.
 fn preprocess(asm: &str) -> Result<String> {
     let commands = asm.split('\n');
     let mut program = String::new();
@@ -83,10 +189,12 @@ fn preprocess(asm: &str) -> Result<String> {
         let parts: Vec<_> = command.split(' ').collect();
         let command = match parts[0] {
             "#define" => {
-                let var = parts[1];
-                let val = parts[2].parse::<usize>()?;
-                map.insert(var, val);
-                String::new()
+                let var = parts
+                    .get(1)
+                    .ok_or_else(|| anyhow!("Missing name of #define"))?;
+                let val = operand(&parts, 2)?;
+                map.insert(*var, val);
+                String::from("\n")
             }
             _ => format!("{}\n", replace(&parts, &map)),
         };
@@ -95,60 +203,140 @@ fn preprocess(asm: &str) -> Result<String> {
     Ok(program)
 }
 
+// A cell defined as `__scratch` must hold zero between instructions; `set`
+// may then use it for multiplication loops.
+fn find_scratch(asm: &str) -> Result<Option<usize>> {
+    asm.split('\n')
+        .find_map(|command| command.strip_prefix("#define __scratch "))
+        .map(|val| val.parse::<usize>())
+        .transpose()
+        .map_err(Into::into)
+}
+
+// Track the values of cells relative to the head through straight-line code,
+// so that `set` can adjust a cell instead of clearing it.
+fn update_known(parts: &[&str], known: &mut HashMap<usize, u8>) -> Result<()> {
+    match parts[0] {
+        "add" | "sub" => {
+            let var = operand(parts, 1)?;
+            let val = (operand(parts, 2)? % 256) as u8;
+            if let Some(current) = known.get_mut(&var) {
+                *current = if parts[0] == "add" {
+                    current.wrapping_add(val)
+                } else {
+                    current.wrapping_sub(val)
+                };
+            }
+        }
+        "set" => {
+            let var = operand(parts, 1)?;
+            let val = (operand(parts, 2)? % 256) as u8;
+            known.insert(var, val);
+        }
+        "end" => {
+            known.clear();
+            known.insert(operand(parts, 1)?, 0);
+        }
+        "copy" => {
+            let src = operand(parts, 1)?;
+            let val = known.insert(src, 0);
+            for part in &parts[2..] {
+                let dest = part.parse::<usize>()?;
+                match val {
+                    Some(val) => known.insert(dest, val),
+                    None => known.remove(&dest),
+                };
+            }
+        }
+        "read" => {
+            known.remove(&operand(parts, 1)?);
+        }
+        "rs" | "ls" | "loop" => known.clear(),
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Assemble the output of [`compile`](crate::compile) into Brainfuck.
+///
+/// # Errors
+///
+/// Fails on unknown instructions and on missing or malformed operands, naming
+/// the line of the assembly.
 pub fn assemble(asm: &str) -> Result<String> {
+    assemble_with_map(asm).map(|(program, _)| program)
+}
+
+fn assemble_command(
+    parts: &[&str],
+    scratch: Option<usize>,
+    known: &HashMap<usize, u8>,
+) -> Result<String> {
+    let bf_command = match parts[0] {
+        "add" => {
+            let var = operand(parts, 1)?;
+            let val = operand(parts, 2)?;
+            format!("{}{}{}", ">".repeat(var), "+".repeat(val), "<".repeat(var))
+        }
+        "sub" => {
+            let var = operand(parts, 1)?;
+            let val = operand(parts, 2)?;
+            format!("{}{}{}", ">".repeat(var), "-".repeat(val), "<".repeat(var))
+        }
+        "set" => {
+            let var = operand(parts, 1)?;
+            let val = (operand(parts, 2)? % 256) as u8;
+            let scratch = scratch.filter(|s| known.get(s).is_none_or(|v| *v == 0));
+            load_constant(var, val, known.get(&var).copied(), scratch)
+        }
+        "rs" => ">".repeat(operand(parts, 1)?),
+        "ls" => "<".repeat(operand(parts, 1)?),
+        "loop" => format!(
+            "{}[{}",
+            ">".repeat(operand(parts, 1)?),
+            "<".repeat(operand(parts, 1)?)
+        ),
+        "end" => format!(
+            "{}]{}",
+            ">".repeat(operand(parts, 1)?),
+            "<".repeat(operand(parts, 1)?)
+        ),
+        "copy" => parse_copy(parts)?,
+        "read" => {
+            let var = operand(parts, 1)?;
+            format!("{},{}", ">".repeat(var), "<".repeat(var))
+        }
+        "write" => {
+            let var = operand(parts, 1)?;
+            format!("{}.{}", ">".repeat(var), "<".repeat(var))
+        }
+        "" | "#" => String::new(),
+        s => return Err(anyhow!("Unknown instruction {}", s)),
+    };
+    Ok(bf_command)
+}
+
+// Also returns, for every character of the program, the index of the line of
+// `asm` it was assembled from.
+pub fn assemble_with_map(asm: &str) -> Result<(String, Vec<usize>)> {
+    let scratch = find_scratch(asm)?;
     let asm = preprocess(asm)?;
     let commands = asm.split('\n');
     let mut program = String::new();
-    for command in commands {
+    let mut lines = vec![];
+    let mut known = HashMap::new();
+    for (line, command) in commands.enumerate() {
         let parts: Vec<_> = command.split(' ').collect();
-        let bf_command = match parts[0] {
-            "add" => {
-                let var = parts[1].parse::<usize>()?;
-                let val = parts[2].parse::<usize>()?;
-                format!("{}{}{}", ">".repeat(var), "+".repeat(val), "<".repeat(var))
-            }
-            "sub" => {
-                let var = parts[1].parse::<usize>()?;
-                let val = parts[2].parse::<usize>()?;
-                format!("{}{}{}", ">".repeat(var), "-".repeat(val), "<".repeat(var))
-            }
-            "set" => {
-                let var = parts[1].parse::<usize>()?;
-                let val = parts[2].parse::<usize>()?;
-                format!(
-                    "{}[-]{}{}",
-                    ">".repeat(var),
-                    "+".repeat(val),
-                    "<".repeat(var)
-                )
-            }
-            "rs" => ">".repeat(parts[1].parse::<usize>()?),
-            "ls" => "<".repeat(parts[1].parse::<usize>()?),
-            "loop" => format!(
-                "{}[{}",
-                ">".repeat(parts[1].parse::<usize>()?),
-                "<".repeat(parts[1].parse::<usize>()?)
-            ),
-            "end" => format!(
-                "{}]{}",
-                ">".repeat(parts[1].parse::<usize>()?),
-                "<".repeat(parts[1].parse::<usize>()?)
-            ),
-            "copy" => parse_copy(&parts[1..])?,
-            "read" => {
-                let var = parts[1].parse::<usize>()?;
-                format!("{},{}", ">".repeat(var), "<".repeat(var))
-            }
-            "write" => {
-                let var = parts[1].parse::<usize>()?;
-                format!("{}.{}", ">".repeat(var), "<".repeat(var))
-            }
-            "" | "#" => String::new(),
-            s => todo!("'{}' not implemented", s),
-        };
+        let bf_command = assemble_command(&parts, scratch, &known)
+            .and_then(|bf_command| {
+                update_known(&parts, &mut known)?;
+                Ok(bf_command)
+            })
+            .map_err(|err| err.context(format!("in line {} of the assembly", line + 1)))?;
+        lines.resize(lines.len() + bf_command.len(), line);
         program.push_str(&bf_command);
     }
-    Ok(program)
+    Ok((program, lines))
 }
 
 #[cfg(test)]
@@ -192,6 +380,48 @@ mod asm {
         assert_eq!(output, expect);
     }
     #[test]
+    fn test_set_multiply() {
+        let asm = "#define __scratch 1\nset 2 89";
+        let expect = ">>[-]<++++++++[>+++++++++++<-]>+<<";
+        let output = assemble(asm).unwrap();
+        assert_eq!(output, expect);
+    }
+    #[test]
+    fn test_set_multiply_negative() {
+        let asm = "#define __scratch 1\nset 2 200";
+        let expect = ">>[-]<+++++++[>--------<-]<";
+        let output = assemble(asm).unwrap();
+        assert_eq!(output, expect);
+    }
+    #[test]
+    fn test_set_short_constant() {
+        let asm = "#define __scratch 1\nset 2 250";
+        let expect = ">>[-]------<<";
+        let output = assemble(asm).unwrap();
+        assert_eq!(output, expect);
+    }
+    #[test]
+    fn test_set_known_value() {
+        let asm = "set 2 5\nadd 2 1\nset 2 3\nset 2 3";
+        let expect = ">>[-]+++++<<>>+<<>>---<<";
+        let output = assemble(asm).unwrap();
+        assert_eq!(output, expect);
+    }
+    #[test]
+    fn test_set_after_loop_and_copy() {
+        let asm = "loop 2\nend 2\nset 2 2\ncopy 2 3\nset 3 3\nrs 1\nset 3 1";
+        let expect = ">>[<<>>]<<>>++<<>>>[-]<<<>>[-<<>>>+<<<>>]<<>>>+<<<>>>>[-]+<<<";
+        let output = assemble(asm).unwrap();
+        assert_eq!(output, expect);
+    }
+    #[test]
+    fn test_map() {
+        let asm = "#define x 1\n# stmt 1:1\nadd x 2\n# end stmt\nrs 1";
+        let (program, lines) = assemble_with_map(asm).unwrap();
+        assert_eq!(program, ">++<>");
+        assert_eq!(lines, vec![2, 2, 2, 2, 4]);
+    }
+    #[test]
     fn test_rs() {
         let asm = "rs 3";
         let expect = ">>>";
@@ -229,7 +459,7 @@ mod asm {
     #[test]
     fn test_preprocess() {
         let asm = "#define a 3\nadd a 2";
-        let expect = "add 3 2\n";
+        let expect = "\nadd 3 2\n";
         let output = preprocess(asm).unwrap();
         assert_eq!(output, expect);
     }
@@ -254,4 +484,21 @@ mod asm {
         let output = assemble(asm).unwrap();
         assert_eq!(output, expect);
     }
+    #[test]
+    fn test_errors() {
+        for asm in ["jump 1", "add 1", "copy", "rs x", "#define a"] {
+            assert!(assemble(asm).is_err(), "{}", asm);
+        }
+        let err = assemble("rs 1\nmov 1 2").unwrap_err();
+        assert_eq!(err.to_string(), "in line 2 of the assembly");
+        assert_eq!(
+            format!("{:#}", err),
+            "in line 2 of the assembly: Unknown instruction mov"
+        );
+        let err = assemble("add 1").unwrap_err();
+        assert_eq!(
+            format!("{:#}", err),
+            "in line 1 of the assembly: Missing operand of add"
+        );
+    }
 }
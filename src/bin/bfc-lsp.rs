@@ -0,0 +1,245 @@
+// Language server for bfc over stdio.
+//
+// Documents are synchronized in full on every change. Positions are converted
+// between the UTF-16 columns of the protocol and the character columns of the
+// compiler.
+
+use anyhow::{anyhow, Result};
+use bf_compiler::compiler::format_program;
+use bf_compiler::ide::{definition, hover, problems, references, Severity};
+use bf_compiler::scanner;
+use lsp_server::{Connection, Message, Notification, Request, Response};
+use lsp_types::notification::{
+    DidChangeTextDocument, DidCloseTextDocument, DidOpenTextDocument,
+    Notification as NotificationTrait, PublishDiagnostics,
+};
+use lsp_types::request::Request as RequestTrait;
+use lsp_types::request::{Formatting, GotoDefinition, HoverRequest, References};
+use lsp_types::{
+    Diagnostic, DiagnosticSeverity, DidChangeTextDocumentParams, DidCloseTextDocumentParams,
+    DidOpenTextDocumentParams, DocumentFormattingParams, GotoDefinitionParams,
+    GotoDefinitionResponse, Hover, HoverContents, HoverParams, HoverProviderCapability, Location,
+    MarkupContent, MarkupKind, OneOf, Position, PublishDiagnosticsParams, Range, ReferenceParams,
+    ServerCapabilities, TextDocumentSyncCapability, TextDocumentSyncKind, TextEdit, Url,
+};
+use std::collections::HashMap;
+
+fn to_location(text: &str, position: Position) -> scanner::Location {
+    let line = text.lines().nth(position.line as usize).unwrap_or("");
+    let mut units = 0;
+    let column = line
+        .chars()
+        .take_while(|c| {
+            units += c.len_utf16();
+            units <= position.character as usize
+        })
+        .count();
+    scanner::Location {
+        line: position.line as usize + 1,
+        column: column + 1,
+    }
+}
+
+fn to_position(text: &str, location: scanner::Location) -> Position {
+    let line = text.lines().nth(location.line - 1).unwrap_or("");
+    let character = line
+        .chars()
+        .take(location.column - 1)
+        .map(char::len_utf16)
+        .sum::<usize>();
+    Position::new(location.line as u32 - 1, character as u32)
+}
+
+// The range of `length` characters starting at `location`.
+fn to_range(text: &str, location: scanner::Location, length: usize) -> Range {
+    let end = scanner::Location {
+        column: location.column + length,
+        ..location
+    };
+    Range::new(to_position(text, location), to_position(text, end))
+}
+
+// The range of the identifier starting at `location`.
+fn identifier_range(text: &str, location: scanner::Location) -> Range {
+    let line = text.lines().nth(location.line - 1).unwrap_or("");
+    let length = line
+        .chars()
+        .skip(location.column - 1)
+        .take_while(|c| c.is_alphanumeric() || *c == '_')
+        .count();
+    to_range(text, location, length)
+}
+
+struct Server {
+    connection: Connection,
+    documents: HashMap<Url, String>,
+}
+
+impl Server {
+    fn document(&self, uri: &Url) -> Result<&str> {
+        self.documents
+            .get(uri)
+            .map(|text| text.as_str())
+            .ok_or_else(|| anyhow!("Unknown document {}", uri))
+    }
+    fn publish_diagnostics(&self, uri: Url) -> Result<()> {
+        let text = self.documents.get(&uri).map_or("", |text| text.as_str());
+        let diagnostics = if self.documents.contains_key(&uri) {
+            problems(text)
+        } else {
+            vec![]
+        };
+        let diagnostics = diagnostics
+            .into_iter()
+            .map(|problem| Diagnostic {
+                range: to_range(text, problem.location, problem.length),
+                severity: Some(match problem.severity {
+                    Severity::Error => DiagnosticSeverity::ERROR,
+                    Severity::Warning => DiagnosticSeverity::WARNING,
+                }),
+                source: Some("bfc".to_string()),
+                message: problem.message,
+                ..Diagnostic::default()
+            })
+            .collect();
+        let params = PublishDiagnosticsParams::new(uri, diagnostics, None);
+        let notification = Notification::new(PublishDiagnostics::METHOD.to_string(), params);
+        self.connection.sender.send(notification.into())?;
+        Ok(())
+    }
+    fn notification(&mut self, notification: Notification) -> Result<()> {
+        match notification.method.as_str() {
+            DidOpenTextDocument::METHOD => {
+                let params: DidOpenTextDocumentParams =
+                    serde_json::from_value(notification.params)?;
+                let uri = params.text_document.uri;
+                self.documents
+                    .insert(uri.clone(), params.text_document.text);
+                self.publish_diagnostics(uri)
+            }
+            DidChangeTextDocument::METHOD => {
+                let params: DidChangeTextDocumentParams =
+                    serde_json::from_value(notification.params)?;
+                let uri = params.text_document.uri;
+                if let Some(change) = params.content_changes.into_iter().last() {
+                    self.documents.insert(uri.clone(), change.text);
+                }
+                self.publish_diagnostics(uri)
+            }
+            DidCloseTextDocument::METHOD => {
+                let params: DidCloseTextDocumentParams =
+                    serde_json::from_value(notification.params)?;
+                self.documents.remove(&params.text_document.uri);
+                self.publish_diagnostics(params.text_document.uri)
+            }
+            _ => Ok(()),
+        }
+    }
+    fn request(&self, request: Request) -> Result<serde_json::Value> {
+        let result = match request.method.as_str() {
+            GotoDefinition::METHOD => {
+                let params: GotoDefinitionParams = serde_json::from_value(request.params)?;
+                let position = params.text_document_position_params;
+                let uri = position.text_document.uri;
+                let text = self.document(&uri)?;
+                let response = definition(text, to_location(text, position.position)).map(|d| {
+                    GotoDefinitionResponse::Scalar(Location::new(
+                        uri.clone(),
+                        identifier_range(text, d),
+                    ))
+                });
+                serde_json::to_value(response)?
+            }
+            References::METHOD => {
+                let params: ReferenceParams = serde_json::from_value(request.params)?;
+                let position = params.text_document_position;
+                let uri = position.text_document.uri;
+                let text = self.document(&uri)?;
+                let response = references(text, to_location(text, position.position))
+                    .into_iter()
+                    .map(|r| Location::new(uri.clone(), identifier_range(text, r)))
+                    .collect::<Vec<_>>();
+                serde_json::to_value(response)?
+            }
+            HoverRequest::METHOD => {
+                let params: HoverParams = serde_json::from_value(request.params)?;
+                let position = params.text_document_position_params;
+                let text = self.document(&position.text_document.uri)?;
+                let response =
+                    hover(text, to_location(text, position.position)).map(|value| Hover {
+                        contents: HoverContents::Markup(MarkupContent {
+                            kind: MarkupKind::Markdown,
+                            value,
+                        }),
+                        range: None,
+                    });
+                serde_json::to_value(response)?
+            }
+            Formatting::METHOD => {
+                let params: DocumentFormattingParams = serde_json::from_value(request.params)?;
+                let text = self.document(&params.text_document.uri)?;
+                // A program that does not parse is left as it is.
+                let response = format_program(text).ok().map(|formatted| {
+                    let lines = text.split('\n').count() as u32;
+                    let end = Position::new(lines, 0);
+                    vec![TextEdit::new(
+                        Range::new(Position::new(0, 0), end),
+                        formatted,
+                    )]
+                });
+                serde_json::to_value(response)?
+            }
+            method => return Err(anyhow!("Unsupported request {}", method)),
+        };
+        Ok(result)
+    }
+    fn run(&mut self) -> Result<()> {
+        while let Ok(message) = self.connection.receiver.recv() {
+            match message {
+                Message::Request(request) => {
+                    if self.connection.handle_shutdown(&request)? {
+                        return Ok(());
+                    }
+                    let id = request.id.clone();
+                    let response = match self.request(request) {
+                        Ok(result) => Response::new_ok(id, result),
+                        Err(err) => Response::new_err(
+                            id,
+                            lsp_server::ErrorCode::RequestFailed as i32,
+                            err.to_string(),
+                        ),
+                    };
+                    self.connection.sender.send(response.into())?;
+                }
+                Message::Notification(notification) => {
+                    if let Err(err) = self.notification(notification) {
+                        eprintln!("error: {}", err);
+                    }
+                }
+                Message::Response(_) => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+fn main() -> Result<()> {
+    let (connection, io_threads) = Connection::stdio();
+    let capabilities = ServerCapabilities {
+        text_document_sync: Some(TextDocumentSyncCapability::Kind(TextDocumentSyncKind::FULL)),
+        definition_provider: Some(OneOf::Left(true)),
+        references_provider: Some(OneOf::Left(true)),
+        hover_provider: Some(HoverProviderCapability::Simple(true)),
+        document_formatting_provider: Some(OneOf::Left(true)),
+        ..ServerCapabilities::default()
+    };
+    connection.initialize(serde_json::to_value(capabilities)?)?;
+    let mut server = Server {
+        connection,
+        documents: HashMap::new(),
+    };
+    server.run()?;
+    drop(server);
+    io_threads.join()?;
+    Ok(())
+}
@@ -0,0 +1,225 @@
+// Construction of bfc programs in memory.
+//
+// The builder owns its variable names, so programs can be assembled from
+// computed strings without rendering and re-scanning source text. The AST it
+// produces owns its names as well, and they are validated the same way the
+// parser validates source.
+
+use crate::parser::{Bool, Compare, Direction, Function, Num, Statement, Variable, AST};
+use anyhow::Result;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum Op {
+    EQ,
+    NE,
+}
+
+/// A conjunction of comparisons of variables against constants, the
+/// condition of `if` and `while`.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Condition {
+    compares: Vec<(String, Op, u8)>,
+}
+
+impl Condition {
+    /// `var == val`
+    pub fn eq(var: &str, val: u8) -> Self {
+        Self {
+            compares: vec![(var.to_string(), Op::EQ, val)],
+        }
+    }
+    /// `var != val`
+    pub fn ne(var: &str, val: u8) -> Self {
+        Self {
+            compares: vec![(var.to_string(), Op::NE, val)],
+        }
+    }
+    /// This condition `&& var == val`.
+    pub fn and_eq(mut self, var: &str, val: u8) -> Self {
+        self.compares.push((var.to_string(), Op::EQ, val));
+        self
+    }
+    /// This condition `&& var != val`.
+    pub fn and_ne(mut self, var: &str, val: u8) -> Self {
+        self.compares.push((var.to_string(), Op::NE, val));
+        self
+    }
+    fn to_bool(&self) -> Result<Bool<'static>> {
+        let compares = self
+            .compares
+            .iter()
+            .map(|(var, op, val)| {
+                let var = variable(var)?;
+                Ok(match op {
+                    Op::EQ => Compare::EQ(var, Num::from(*val)),
+                    Op::NE => Compare::NE(var, Num::from(*val)),
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Bool::new(compares))
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+enum Node {
+    If(Condition, Block, Option<Block>),
+    While(Condition, Block),
+    Assign(String, u8),
+    Move(Direction),
+    Input(String),
+    Output(String),
+    Carry(String),
+}
+
+/// A sequence of statements: a whole program, or the body of `if`, `else`
+/// and `while`.
+///
+/// ```
+/// use bf_compiler::{Block, Condition};
+///
+/// let mut body = Block::new();
+/// body.output("c").input("c");
+/// let mut program = Block::new();
+/// program.input("c").while_loop(Condition::ne("c", 0), body);
+/// assert_eq!(
+///     program.ast()?.to_string(),
+///     "input ( c )\nwhile c != 0 {\n  output ( c )\n  input ( c )\n}\n"
+/// );
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct Block {
+    nodes: Vec<Node>,
+}
+
+fn variable(name: &str) -> Result<Variable<'static>> {
+    Variable::new(name.to_string())
+}
+
+impl Block {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// `var = val`
+    pub fn assign(&mut self, var: &str, val: u8) -> &mut Self {
+        self.nodes.push(Node::Assign(var.to_string(), val));
+        self
+    }
+    /// `input ( var )`
+    pub fn input(&mut self, var: &str) -> &mut Self {
+        self.nodes.push(Node::Input(var.to_string()));
+        self
+    }
+    /// `output ( var )`
+    pub fn output(&mut self, var: &str) -> &mut Self {
+        self.nodes.push(Node::Output(var.to_string()));
+        self
+    }
+    /// `carry ( var )`
+    pub fn carry(&mut self, var: &str) -> &mut Self {
+        self.nodes.push(Node::Carry(var.to_string()));
+        self
+    }
+    /// `move_left`
+    pub fn move_left(&mut self) -> &mut Self {
+        self.nodes.push(Node::Move(Direction::Left));
+        self
+    }
+    /// `move_right`
+    pub fn move_right(&mut self) -> &mut Self {
+        self.nodes.push(Node::Move(Direction::Right));
+        self
+    }
+    /// `if cond { then }`
+    pub fn if_then(&mut self, cond: Condition, then: Block) -> &mut Self {
+        self.nodes.push(Node::If(cond, then, None));
+        self
+    }
+    /// `if cond { then } else { otherwise }`
+    pub fn if_else(&mut self, cond: Condition, then: Block, otherwise: Block) -> &mut Self {
+        self.nodes.push(Node::If(cond, then, Some(otherwise)));
+        self
+    }
+    /// `while cond { body }`
+    pub fn while_loop(&mut self, cond: Condition, body: Block) -> &mut Self {
+        self.nodes.push(Node::While(cond, body));
+        self
+    }
+    fn to_function(&self) -> Result<Function<'static>> {
+        let statements = self
+            .nodes
+            .iter()
+            .map(|node| {
+                Ok(match node {
+                    Node::If(cond, then, otherwise) => Statement::IF(
+                        cond.to_bool()?,
+                        then.to_function()?,
+                        otherwise.as_ref().map(Block::to_function).transpose()?,
+                    ),
+                    Node::While(cond, body) => {
+                        Statement::WHILE(cond.to_bool()?, body.to_function()?)
+                    }
+                    Node::Assign(var, val) => Statement::Assign(variable(var)?, Num::from(*val)),
+                    Node::Move(Direction::Left) => {
+                        Statement::Move(Direction::Left, "move_left".into())
+                    }
+                    Node::Move(Direction::Right) => {
+                        Statement::Move(Direction::Right, "move_right".into())
+                    }
+                    Node::Input(var) => Statement::Input(variable(var)?),
+                    Node::Output(var) => Statement::Output(variable(var)?),
+                    Node::Carry(var) => Statement::Carry(variable(var)?),
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Function::new(statements))
+    }
+    /// The program as a syntax tree, for [`compile_ast`](crate::compile_ast).
+    ///
+    /// # Errors
+    ///
+    /// Fails if a variable name would not be accepted in source, because it
+    /// is not an identifier or is a reserved word.
+    pub fn ast(&self) -> Result<AST<'static>> {
+        Ok(AST::new(self.to_function()?))
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::module_inception)]
+mod builder {
+    use super::*;
+    use crate::scanner::TokenStream;
+
+    #[test]
+    fn test_parsed_equal() {
+        let source = "carry ( s ) s = 2 while s != 0 && x == 0 { if s == 2 { s = 1 move_right } else { s = 0 move_left } } output ( s )";
+        let tokens = TokenStream::try_from(source).unwrap();
+        let parsed = AST::try_from(tokens.tokens()).unwrap();
+
+        let mut then = Block::new();
+        then.assign("s", 1).move_right();
+        let mut otherwise = Block::new();
+        otherwise.assign("s", 0).move_left();
+        let mut body = Block::new();
+        body.if_else(Condition::eq("s", 2), then, otherwise);
+        let mut program = Block::new();
+        program
+            .carry("s")
+            .assign("s", 2)
+            .while_loop(Condition::ne("s", 0).and_eq("x", 0), body)
+            .output("s");
+        assert_eq!(program.ast().unwrap(), parsed);
+    }
+    #[test]
+    fn test_invalid_names() {
+        for name in ["while", "1x", "a b", "", "=="] {
+            let mut program = Block::new();
+            program.output(name);
+            assert!(program.ast().is_err(), "{}", name);
+        }
+        let mut program = Block::new();
+        program.if_then(Condition::eq("ok", 1).and_ne("else", 0), Block::new());
+        assert!(program.ast().is_err());
+    }
+}
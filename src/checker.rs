@@ -0,0 +1,250 @@
+// Variable initialization checks.
+//
+// Both passes ignore frames: a variable counts as written once it has been
+// written in any frame. A move may bring the head back to a frame written
+// earlier, so every variable is considered read after a move. Arrays start
+// out as zeros and are not checked, only the variables of their indexes and
+// of the values stored into them are.
+
+use crate::analyzer::Diagnostic;
+use crate::generator::list_variables;
+use crate::parser::{Bool, Statement, Variable, AST};
+use crate::scanner::Location;
+use std::collections::HashSet;
+
+fn bool_variables<'a>(cond: &'a Bool<'a>) -> impl Iterator<Item = &'a Variable<'a>> {
+    cond.compares().iter().flat_map(|c| c.variables())
+}
+
+fn read<'a>(
+    var: &'a Variable<'a>,
+    written: &HashSet<&'a str>,
+    unwritten: &mut Option<&mut Vec<&'a Variable<'a>>>,
+) {
+    if let Some(unwritten) = unwritten {
+        if !written.contains(&**var) {
+            unwritten.push(var);
+        }
+    }
+}
+
+// Forward pass over the variables that may have been written so far. Reads of
+// anything outside that set are collected into `unwritten`.
+fn written<'a>(
+    statements: &'a [Statement<'a>],
+    mut written: HashSet<&'a str>,
+    unwritten: &mut Option<&mut Vec<&'a Variable<'a>>>,
+) -> HashSet<&'a str> {
+    for stmt in statements {
+        match stmt {
+            Statement::Assign(var, _) | Statement::Input(var) => {
+                written.insert(var);
+            }
+            Statement::Compute(var, expr) => {
+                for read_var in expr.variables() {
+                    read(read_var, &written, unwritten);
+                }
+                written.insert(var);
+            }
+            Statement::Store(_, index, expr) => {
+                for read_var in [index.variables(), expr.variables()].concat() {
+                    read(read_var, &written, unwritten);
+                }
+            }
+            Statement::Output(var) => read(var, &written, unwritten),
+            Statement::IF(cond, if_func, else_func) => {
+                bool_variables(cond).for_each(|var| read(var, &written, unwritten));
+                let mut after = self::written(if_func.statements(), written.clone(), unwritten);
+                if let Some(else_func) = else_func {
+                    after.extend(self::written(else_func.statements(), written, unwritten));
+                }
+                written = after;
+            }
+            Statement::WHILE(cond, func) => {
+                loop {
+                    let after = self::written(func.statements(), written.clone(), &mut None);
+                    if after.is_subset(&written) {
+                        break;
+                    }
+                    written.extend(after);
+                }
+                bool_variables(cond).for_each(|var| read(var, &written, unwritten));
+                self::written(func.statements(), written.clone(), unwritten);
+            }
+            Statement::Move(..) | Statement::Carry(_) | Statement::Let(..) => {}
+        }
+    }
+    written
+}
+
+// Backward pass over the variables that may still be read. Assignments to
+// anything outside that set are collected into `dead`.
+fn live<'a>(
+    statements: &'a [Statement<'a>],
+    mut live: HashSet<String>,
+    variables: &HashSet<String>,
+    dead: &mut Option<&mut Vec<&'a Variable<'a>>>,
+) -> HashSet<String> {
+    for stmt in statements.iter().rev() {
+        match stmt {
+            Statement::Assign(var, _) | Statement::Compute(var, _) => {
+                if let Some(dead) = dead {
+                    if !live.contains(&**var) {
+                        dead.push(var);
+                    }
+                }
+                live.remove(&**var);
+                if let Statement::Compute(_, expr) = stmt {
+                    live.extend(expr.variables().iter().map(|var| var.to_string()));
+                }
+            }
+            Statement::Store(_, index, expr) => {
+                live.extend(
+                    [index.variables(), expr.variables()]
+                        .concat()
+                        .iter()
+                        .map(|var| var.to_string()),
+                );
+            }
+            Statement::Input(var) => {
+                live.remove(&**var);
+            }
+            Statement::Output(var) => {
+                live.insert(var.to_string());
+            }
+            Statement::Move(..) => live = variables.clone(),
+            Statement::IF(cond, if_func, else_func) => {
+                let mut before = self::live(if_func.statements(), live.clone(), variables, dead);
+                if let Some(else_func) = else_func {
+                    before.extend(self::live(else_func.statements(), live, variables, dead));
+                } else {
+                    before.extend(live);
+                }
+                before.extend(bool_variables(cond).map(|var| var.to_string()));
+                live = before;
+            }
+            Statement::WHILE(cond, func) => {
+                live.extend(bool_variables(cond).map(|var| var.to_string()));
+                loop {
+                    let before = self::live(func.statements(), live.clone(), variables, &mut None);
+                    if before.is_subset(&live) {
+                        break;
+                    }
+                    live.extend(before);
+                }
+                self::live(func.statements(), live.clone(), variables, dead);
+            }
+            Statement::Carry(_) | Statement::Let(..) => {}
+        }
+    }
+    live
+}
+
+fn diagnostic(var: &Variable, source: &str, message: String) -> Diagnostic {
+    Diagnostic {
+        location: Location::find(source, var),
+        message,
+    }
+}
+
+// Warn about reads of variables that are never written before and about
+// assignments that are never read afterwards. `source` is the text `ast` was
+// parsed from.
+pub fn check_initialization(ast: &AST, source: &str) -> Vec<Diagnostic> {
+    let mut unwritten = vec![];
+    written(ast.statements(), HashSet::new(), &mut Some(&mut unwritten));
+    let mut dead = vec![];
+    let variables = list_variables(ast);
+    live(
+        ast.statements(),
+        HashSet::new(),
+        &variables,
+        &mut Some(&mut dead),
+    );
+    let unwritten = unwritten.into_iter().map(|var| {
+        let message = format!("`{}` is read before it is ever written", &**var);
+        diagnostic(var, source, message)
+    });
+    let dead = dead.into_iter().map(|var| {
+        let message = format!("value assigned to `{}` is never read", &**var);
+        diagnostic(var, source, message)
+    });
+    let mut diagnostics = unwritten.chain(dead).collect::<Vec<_>>();
+    diagnostics.sort_by_key(|d| d.location.map(|l| (l.line, l.column)));
+    diagnostics
+}
+
+#[cfg(test)]
+#[allow(clippy::module_inception)]
+mod checker {
+    use super::*;
+    use crate::scanner::TokenStream;
+    fn check(program: &str) -> Vec<(usize, usize, String)> {
+        let tokens = TokenStream::try_from(program).unwrap();
+        let ast = AST::try_from(&*tokens.into_tokens()).unwrap();
+        check_initialization(&ast, program)
+            .into_iter()
+            .map(|d| {
+                let location = d.location.unwrap();
+                (location.line, location.column, d.message)
+            })
+            .collect()
+    }
+    #[test]
+    fn test_unwritten_read() {
+        let program = "output ( x )\nx = 1\noutput ( x )";
+        let expect = vec![(1, 10, "`x` is read before it is ever written".to_string())];
+        assert_eq!(check(program), expect);
+    }
+    #[test]
+    fn test_unwritten_condition() {
+        let program = "if x == 1 { y = 1 }\noutput ( y )";
+        let expect = vec![(1, 4, "`x` is read before it is ever written".to_string())];
+        assert_eq!(check(program), expect);
+    }
+    #[test]
+    fn test_unwritten_order() {
+        let program = "x = 1\nif x < y { x = 2 }\noutput ( x )";
+        let expect = vec![(2, 8, "`y` is read before it is ever written".to_string())];
+        assert_eq!(check(program), expect);
+    }
+    #[test]
+    fn test_expression() {
+        let program = "x = 1\ny = x + z\nx = y * 2\nx = y";
+        let expect = vec![
+            (2, 9, "`z` is read before it is ever written".to_string()),
+            (3, 1, "value assigned to `x` is never read".to_string()),
+            (4, 1, "value assigned to `x` is never read".to_string()),
+        ];
+        assert_eq!(check(program), expect);
+    }
+    #[test]
+    fn test_written_in_branch() {
+        let program = "input ( c )\nif c == 1 { x = 1 }\noutput ( x )";
+        assert_eq!(check(program), vec![]);
+    }
+    #[test]
+    fn test_written_in_loop() {
+        let program = "input ( c )\nwhile c != 0 { if c == 2 { output ( x ) } x = 1 input ( c ) }";
+        assert_eq!(check(program), vec![]);
+    }
+    #[test]
+    fn test_dead_assign() {
+        let program = "x = 1\nx = 2\noutput ( x )\ny = 3";
+        let expect = vec![
+            (1, 1, "value assigned to `x` is never read".to_string()),
+            (4, 1, "value assigned to `y` is never read".to_string()),
+        ];
+        assert_eq!(check(program), expect);
+    }
+    #[test]
+    fn test_assign_read_by_loop() {
+        let program = "s = 1\nwhile s != 0 { output ( s ) input ( s ) }";
+        assert_eq!(check(program), vec![]);
+    }
+    #[test]
+    fn test_assign_before_move() {
+        let program = "x = 1\nmove_right\nx = 2\nmove_left\noutput ( x )";
+        assert_eq!(check(program), vec![]);
+    }
+}
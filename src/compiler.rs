@@ -1,11 +1,378 @@
+use crate::analyzer::{check_movement, left_excursion, reach, Bounds, Diagnostic};
+use crate::assembler::assemble;
+use crate::checker::check_initialization;
+use crate::evaluator::Evaluator;
+use crate::formatter::format;
 use crate::generator::code_gen;
+use crate::interpreter::Interpreter;
+use crate::layout::Layout;
+use crate::optimizer::optimize;
 use crate::parser::AST;
 use crate::scanner::TokenStream;
-use anyhow::{Result};
+use anyhow::{anyhow, Result};
+use std::collections::BTreeSet;
 
-pub fn compile(program: &str) -> Result<String> {
+/// Options of [`compile`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompileOptions {
+    /// Frames to shift the head right before the program starts, so that it
+    /// can move left of its starting frame. Without it the head is shifted
+    /// just enough for the leftmost frame the program can reach.
+    pub tape_origin: Option<usize>,
+    /// Remove assignments of values a variable already holds and branches
+    /// whose condition is known. On by default.
+    pub optimize: bool,
+}
+
+impl Default for CompileOptions {
+    fn default() -> Self {
+        Self {
+            tape_origin: None,
+            optimize: true,
+        }
+    }
+}
+
+/// The result of [`compile`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct Compilation {
+    /// The generated assembly, to be turned into Brainfuck by
+    /// [`assemble`].
+    pub asm: String,
+    /// Problems that do not prevent compiling the program, such as a tape
+    /// origin that is too small for the program.
+    pub warnings: Vec<String>,
+}
+
+/// Compile bfc source code to assembly.
+///
+/// # Errors
+///
+/// Fails if the source does not scan or parse, or uses a reserved variable
+/// name.
+pub fn compile(program: &str, options: &CompileOptions) -> Result<Compilation> {
     let tokens = TokenStream::try_from(program)?;
-    let tokens = tokens.into_tokens();
-    let ast = AST::try_from(&*tokens)?;
-    code_gen(&ast)
+    let ast = AST::try_from(&tokens)?;
+    compile_parsed(&ast, Some(program), options)
+}
+
+/// Compile a syntax tree to assembly, such as one built with
+/// [`Block`](crate::Block).
+///
+/// # Errors
+///
+/// Fails if the program uses a reserved variable name.
+pub fn compile_ast(ast: &AST, options: &CompileOptions) -> Result<Compilation> {
+    compile_parsed(ast, None, options)
+}
+
+// `source` is the text `ast` was parsed from, to mark the code of every
+// statement with its location.
+fn compile_parsed(
+    ast: &AST,
+    source: Option<&str>,
+    options: &CompileOptions,
+) -> Result<Compilation> {
+    let ast = if options.optimize {
+        optimize(ast)
+    } else {
+        ast.clone()
+    };
+    let mut warnings = vec![];
+    let (excursion, unbounded) = left_excursion(&ast);
+    if unbounded {
+        warnings.push(format!(
+            "the program may move unboundedly far left of its starting frame, \
+             at least {} frame(s) are needed, use a larger tape origin to reserve more",
+            excursion
+        ));
+    }
+    let tape_origin = match options.tape_origin {
+        Some(origin) if origin < excursion => {
+            warnings.push(format!(
+                "tape origin {} is less than the {} frame(s) the program moves left",
+                origin, excursion
+            ));
+            origin
+        }
+        Some(origin) => origin,
+        None => excursion,
+    };
+    let asm = code_gen(&ast, source, tape_origin)?;
+    Ok(Compilation { asm, warnings })
+}
+
+// Static diagnostics of a program, without generating any code.
+pub fn check(program: &str) -> Result<(Bounds, Vec<Diagnostic>)> {
+    let tokens = TokenStream::try_from(program)?;
+    let ast = AST::try_from(&tokens)?;
+    let diagnostics = [
+        check_movement(&ast, program),
+        check_initialization(&ast, program),
+    ]
+    .concat();
+    Ok((reach(&ast), diagnostics))
+}
+
+// The program in canonical formatting, keeping its blank lines.
+pub fn format_program(program: &str) -> Result<String> {
+    let tokens = TokenStream::try_from(program)?;
+    let ast = AST::try_from(&tokens)?;
+    Ok(format(&ast, Some(program)))
+}
+
+// Run the program both on the evaluator and compiled on the interpreter, and
+// describe every difference in their output or in the variables left on the
+// tape. `limit` bounds the statements of the evaluator and the instructions
+// of the interpreter; exceeding either is an error rather than a difference.
+pub fn differential(program: &str, input: &[u8], limit: u64) -> Result<Vec<String>> {
+    let tokens = TokenStream::try_from(program)?;
+    let ast = AST::try_from(&tokens)?;
+    let mut evaluator = Evaluator::new(&ast, Some(limit));
+    let mut expect = vec![];
+    evaluator.run(&ast, &mut &*input, &mut expect)?;
+
+    let asm = compile(program, &CompileOptions::default())?.asm;
+    let mut interpreter = Interpreter::new(&assemble(&asm)?)?;
+    let mut output = vec![];
+    let mut input = input;
+    while !interpreter.is_halted() {
+        if interpreter.steps() >= limit {
+            return Err(anyhow!("Exceeded {} instructions", limit));
+        }
+        interpreter.step(&mut input, &mut output)?;
+    }
+
+    let mut differences = vec![];
+    if output != expect {
+        differences.push(format!(
+            "output: expected {:?}, found {:?}",
+            String::from_utf8_lossy(&expect),
+            String::from_utf8_lossy(&output)
+        ));
+    }
+    let layout = Layout::from_asm(&asm)?;
+    let tape = interpreter.tape();
+    let tape_frames = (0..tape.len()).step_by(layout.cell_size);
+    let frames = evaluator
+        .frames()
+        .chain(tape_frames.map(|base| layout.frame(base)))
+        .collect::<BTreeSet<_>>();
+    for frame in frames {
+        for (var, offset) in &layout.variables {
+            let found = (frame + layout.origin as i64)
+                .try_into()
+                .ok()
+                .and_then(|base: usize| tape.get(base * layout.cell_size + offset))
+                .copied()
+                .unwrap_or(0);
+            let expect = evaluator.get(frame, var);
+            if found != expect {
+                differences.push(format!(
+                    "frame {} `{}`: expected {}, found {}",
+                    frame, var, expect, found
+                ));
+            }
+        }
+    }
+    let head = layout.frame(interpreter.head());
+    if head != evaluator.head() {
+        differences.push(format!(
+            "head: expected frame {}, found frame {}",
+            evaluator.head(),
+            head
+        ));
+    }
+    Ok(differences)
+}
+
+#[cfg(test)]
+#[allow(clippy::module_inception)]
+mod compiler {
+    use super::*;
+    #[test]
+    fn test_differential() {
+        let programs = [
+            "input ( c ) while c != 0 { output ( c ) input ( c ) }",
+            "carry ( s ) s = 2 while s != 0 { if s == 2 { x = 5 s = 1 move_right } else { s = 0 move_left } }",
+            "input ( a ) if a == 97 && b == 0 { move_left a = 3 } else { a = 4 }",
+            // A declaration in a branch the optimizer drops still applies.
+            "s = 1 if s == 2 { carry ( s ) } move_right output ( s )",
+        ];
+        for program in programs {
+            assert_eq!(
+                differential(program, b"ab", 10000).unwrap(),
+                Vec::<String>::new()
+            );
+        }
+    }
+    #[test]
+    fn test_expressions() {
+        let ops = ["+", "-", "*", "/", "%", "<", "<=", ">", ">="];
+        let values = [0u8, 1, 3, 7, 10, 255];
+        for op in ops {
+            let program = format!("input ( a ) input ( b ) x = a {} b output ( x )", op);
+            // Multiplication and division loop over their operands.
+            let right = match op {
+                "*" | "/" | "%" => &values[..4],
+                _ => &values[..],
+            };
+            for a in values {
+                for &b in right {
+                    assert_eq!(
+                        differential(&program, &[a, b], 10_000_000).unwrap(),
+                        Vec::<String>::new(),
+                        "{} {} {}",
+                        a,
+                        op,
+                        b
+                    );
+                }
+            }
+        }
+        let program = "input ( a ) x = ( a + 1 ) * ( a - 2 ) % 7 < a output ( x )";
+        assert_eq!(
+            differential(program, &[9], 1_000_000).unwrap(),
+            Vec::<String>::new()
+        );
+    }
+    #[test]
+    fn test_constant_operands() {
+        let values = [0u8, 1, 2, 3, 7, 10, 128, 255];
+        for op in ["+", "-", "*", "/", "%"] {
+            for n in values {
+                let mut program = format!(
+                    "input ( a ) x = a {op} {n} a = a {op} {n} output ( x )",
+                    op = op,
+                    n = n
+                );
+                if op == "+" || op == "*" {
+                    program.push_str(&format!(" y = {} {} x", n, op));
+                }
+                for a in values {
+                    assert_eq!(
+                        differential(&program, &[a], 10_000_000).unwrap(),
+                        Vec::<String>::new(),
+                        "{}",
+                        program
+                    );
+                }
+            }
+        }
+    }
+    #[test]
+    fn test_ordered_conditions() {
+        let program = "input ( a ) input ( b ) r = 0
+            if a < b { r = r + 1 }
+            if a <= b && b > 3 { r = r + 2 } else { r = r + 4 }
+            if b >= a && a != 0 && a > 1 { r = r + 8 }
+            output ( r )
+            n = 0 while n < a && n <= 9 { n = n + 1 output ( n ) }";
+        let values = [0u8, 1, 2, 3, 4, 12, 255];
+        for a in values {
+            for b in values {
+                assert_eq!(
+                    differential(program, &[a, b], 10_000_000).unwrap(),
+                    Vec::<String>::new(),
+                    "{} {}",
+                    a,
+                    b
+                );
+            }
+        }
+    }
+    #[test]
+    fn test_arrays() {
+        let program = "let t [ 5 ]
+            i = 0 while i < 5 { t [ i ] = i * 3 + 1 i = i + 1 }
+            input ( k ) input ( v )
+            t [ k ] = t [ k ] + v
+            x = t [ k ] output ( x )
+            y = t [ k + 1 ] + t [ 4 ] output ( y )
+            t [ 0 ] = k move_right t [ k ] = 9 output ( i )
+            carry ( i ) move_left output ( i )";
+        for k in [0u8, 1, 3, 4, 5, 97, 255] {
+            assert_eq!(
+                differential(program, &[k, 10], 10_000_000).unwrap(),
+                Vec::<String>::new(),
+                "{}",
+                k
+            );
+        }
+        // Declarations in branches the optimizer drops still apply.
+        let programs = [
+            "x = 1 if x == 2 { let t [ 3 ] } t [ 0 ] = 65 y = t [ 0 ] output ( y )",
+            "x = 0 while x != 0 { let t [ 3 ] } t [ 2 ] = 66 y = t [ 2 ] output ( y )",
+        ];
+        for program in programs {
+            assert_eq!(
+                differential(program, b"", 100_000).unwrap(),
+                Vec::<String>::new()
+            );
+        }
+        let options = CompileOptions::default();
+        assert!(compile("let t [ 2 ] t [ 2 ] = 1", &options).is_err());
+        assert!(compile("let t [ 2 ] x = u [ 0 ]", &options).is_err());
+        assert!(compile("let t [ 2 ] let t [ 3 ]", &options).is_err());
+        assert!(compile("let t [ 2 ] t = 1", &options).is_err());
+    }
+    // Before equality chains were lowered as a switch, programs/div.bfc
+    // compiled to 5458 bytes and took 271514 steps.
+    #[test]
+    fn test_equality_switch_cost() {
+        let source = include_str!("../programs/div.bfc");
+        let compilation = compile(source, &CompileOptions::default()).unwrap();
+        let program = assemble(&compilation.asm).unwrap();
+        assert!(program.len() < 5458, "{} bytes", program.len());
+        let mut interpreter = Interpreter::new(&program).unwrap();
+        let mut output = vec![];
+        interpreter.run(&mut [].as_slice(), &mut output).unwrap();
+        assert_eq!(output, include_bytes!("../programs/div.out"));
+        assert!(
+            interpreter.steps() < 271514,
+            "{} steps",
+            interpreter.steps()
+        );
+    }
+    #[test]
+    fn test_options() {
+        let program = "x = 1 x = 1 move_left";
+        let asm = compile(program, &CompileOptions::default()).unwrap().asm;
+        assert_eq!(asm.matches("set ").count(), 1);
+        assert!(asm.contains("#define __tape_origin 1"));
+        let options = CompileOptions {
+            tape_origin: Some(0),
+            optimize: false,
+        };
+        let compilation = compile(program, &options).unwrap();
+        assert_eq!(compilation.asm.matches("set ").count(), 2);
+        assert!(compilation.asm.contains("#define __tape_origin 0"));
+        assert_eq!(compilation.warnings.len(), 1);
+    }
+    #[test]
+    fn test_compile_ast() {
+        let program = "carry ( c ) c = 7 move_right output ( c )";
+        let tokens = TokenStream::try_from(program).unwrap();
+        let ast = AST::try_from(tokens.tokens()).unwrap();
+        let options = CompileOptions::default();
+        let asm = compile_ast(&ast, &options).unwrap().asm;
+        let marked = compile(program, &options).unwrap().asm;
+        let unmarked = marked
+            .split('\n')
+            .filter(|line| !line.starts_with("# "))
+            .collect::<Vec<_>>()
+            .join("\n");
+        assert_eq!(asm, unmarked);
+    }
+    #[test]
+    fn test_errors() {
+        let options = CompileOptions::default();
+        assert!(compile("x = 256", &options).is_err());
+        assert!(compile("x = 1 }", &options).is_err());
+        assert!(compile("tmp = 1", &options).is_err());
+    }
+    #[test]
+    fn test_differential_limit() {
+        assert!(differential("x = 1 while x != 0 { }", b"", 1000).is_err());
+    }
 }
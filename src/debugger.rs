@@ -0,0 +1,229 @@
+// Source level debugger for compiled programs.
+//
+// The program runs on the interpreter and stops whenever it enters the code of
+// another statement, according to the source map. Every assembly instruction
+// starts and ends with the head on the first cell of a frame, so the variables
+// can be read through the layout whenever the program is stopped.
+
+use crate::interpreter::Interpreter;
+use crate::layout::Layout;
+use crate::scanner::Location;
+use crate::sourcemap::SourceMap;
+use anyhow::{anyhow, Result};
+use std::collections::BTreeSet;
+use std::io::{Cursor, Write};
+
+const HELP: &str = "\
+step [N]             run until the N-th next statement (s)
+continue             run until a breakpoint or the end of the program (c)
+break LINE           stop whenever a statement on LINE is entered (b)
+delete LINE          remove the breakpoint on LINE (d)
+print VAR [FRAME]    print a variable of the current frame or of the frame at an offset (p)
+frame [FRAME]        print every variable of the current frame or of the frame at an offset (f)
+tape [on|off]        print the tape as a table of frames, or turn printing it at every stop on or off (t)
+where                print the statement the program is stopped at (w)
+quit                 leave the debugger (q)";
+
+pub struct Debugger<'a> {
+    source: &'a str,
+    interpreter: Interpreter,
+    source_map: SourceMap,
+    layout: Layout,
+    input: Cursor<Vec<u8>>,
+    breakpoints: BTreeSet<usize>,
+    show_tape: bool,
+}
+
+impl<'a> Debugger<'a> {
+    // `asm` is the assembly `program` was assembled from, with `lines` as
+    // returned by `assemble_with_map`. The debugger stops at the first
+    // statement, `input` is fed to the program.
+    pub fn new(
+        source: &'a str,
+        asm: &str,
+        program: &str,
+        lines: &[usize],
+        input: Vec<u8>,
+        output: &mut impl Write,
+    ) -> Result<Self> {
+        let mut debugger = Self {
+            source,
+            interpreter: Interpreter::new(program)?,
+            source_map: SourceMap::new(asm, lines)?,
+            layout: Layout::from_asm(asm)?,
+            input: Cursor::new(input),
+            breakpoints: BTreeSet::new(),
+            show_tape: false,
+        };
+        if debugger.location().is_none() {
+            debugger.run_until(output, |_| true)?;
+        }
+        Ok(debugger)
+    }
+    fn location(&self) -> Option<Location> {
+        self.source_map
+            .find(self.interpreter.pc())
+            .and_then(|m| m.location)
+    }
+    // Run until the program enters a statement `stop` accepts.
+    fn run_until(
+        &mut self,
+        output: &mut impl Write,
+        stop: impl Fn(&Location) -> bool,
+    ) -> Result<()> {
+        let mut previous = self.location();
+        while !self.interpreter.is_halted() {
+            self.interpreter.step(&mut self.input, output)?;
+            let location = self.location();
+            if location != previous {
+                if location.as_ref().is_some_and(&stop) {
+                    break;
+                }
+                previous = location;
+            }
+        }
+        Ok(())
+    }
+    fn frame(&self, offset: &str) -> Result<usize> {
+        let offset = offset.parse::<i64>()?;
+        let frame = (self.interpreter.head() / self.layout.cell_size) as i64 + offset;
+        if frame < 0 {
+            return Err(anyhow!("Frame {} is left of the tape", offset));
+        }
+        Ok(frame as usize)
+    }
+    fn variable(&self, name: &str, frame: usize) -> Result<u8> {
+        let offset = self
+            .layout
+            .offset(name)
+            .ok_or_else(|| anyhow!("No variable named {}", name))?;
+        Ok(self
+            .interpreter
+            .cell(frame * self.layout.cell_size + offset))
+    }
+    fn print_tape(&self, output: &mut impl Write) -> Result<()> {
+        let tape = self.interpreter.tape();
+        write!(
+            output,
+            "{}",
+            self.layout.render(tape, self.interpreter.head())
+        )?;
+        Ok(())
+    }
+    fn print_stop(&self, output: &mut impl Write) -> Result<()> {
+        self.print_location(output)?;
+        if self.show_tape {
+            self.print_tape(output)?;
+        }
+        Ok(())
+    }
+    fn print_location(&self, output: &mut impl Write) -> Result<()> {
+        match self.location() {
+            _ if self.interpreter.is_halted() => writeln!(
+                output,
+                "program halted after {} steps",
+                self.interpreter.steps()
+            )?,
+            Some(location) => {
+                let text = self.source.lines().nth(location.line - 1).unwrap_or("");
+                let frame = self.layout.frame(self.interpreter.head());
+                writeln!(output, "{} (frame {}): {}", location, frame, text.trim())?
+            }
+            None => writeln!(output, "stopped outside of any statement")?,
+        }
+        Ok(())
+    }
+    // Execute one command. Returns `false` once the debugger should quit.
+    pub fn execute(&mut self, command: &str, output: &mut impl Write) -> Result<bool> {
+        let parts = command.split_whitespace().collect::<Vec<_>>();
+        match parts[..] {
+            [] => {}
+            ["step" | "s"] | ["step" | "s", _] => {
+                let count = parts.get(1).map_or(Ok(1), |n| n.parse::<usize>())?;
+                for _ in 0..count {
+                    self.run_until(output, |_| true)?;
+                }
+                self.print_stop(output)?;
+            }
+            ["continue" | "c"] => {
+                let breakpoints = self.breakpoints.clone();
+                self.run_until(output, |l| breakpoints.contains(&l.line))?;
+                self.print_stop(output)?;
+            }
+            ["break" | "b", line] => {
+                self.breakpoints.insert(line.parse()?);
+                writeln!(output, "breakpoint on line {}", line)?;
+            }
+            ["delete" | "d", line] => {
+                if !self.breakpoints.remove(&line.parse()?) {
+                    return Err(anyhow!("No breakpoint on line {}", line));
+                }
+            }
+            ["print" | "p", name] | ["print" | "p", name, _] => {
+                let frame = self.frame(parts.get(2).unwrap_or(&"0"))?;
+                writeln!(output, "{} = {}", name, self.variable(name, frame)?)?;
+            }
+            ["frame" | "f"] | ["frame" | "f", _] => {
+                let frame = self.frame(parts.get(1).unwrap_or(&"0"))?;
+                for (name, _) in &self.layout.variables {
+                    writeln!(output, "{} = {}", name, self.variable(name, frame)?)?;
+                }
+            }
+            ["tape" | "t"] => self.print_tape(output)?,
+            ["tape" | "t", "on"] => self.show_tape = true,
+            ["tape" | "t", "off"] => self.show_tape = false,
+            ["where" | "w"] => self.print_location(output)?,
+            ["help" | "h"] => writeln!(output, "{}", HELP)?,
+            ["quit" | "q"] => return Ok(false),
+            _ => return Err(anyhow!("Unknown command {}, try help", command.trim())),
+        }
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::module_inception)]
+mod debugger {
+    use super::*;
+    use crate::assembler::assemble_with_map;
+    use crate::compiler::{compile, CompileOptions};
+
+    fn debug(source: &str, commands: &[&str]) -> String {
+        let asm = compile(source, &CompileOptions::default()).unwrap().asm;
+        let (program, lines) = assemble_with_map(&asm).unwrap();
+        let mut output = vec![];
+        let mut debugger =
+            Debugger::new(source, &asm, &program, &lines, b"a".to_vec(), &mut output).unwrap();
+        for command in commands {
+            debugger.execute(command, &mut output).unwrap();
+        }
+        String::from_utf8(output).unwrap()
+    }
+    #[test]
+    fn test_step() {
+        let source = "x = 1\ninput ( y )\noutput ( y )";
+        let output = debug(source, &["where", "step", "print y", "step 2"]);
+        let expect = "1:1 (frame 0): x = 1\n2:9 (frame 0): input ( y )\ny = 0\n\
+                      aprogram halted after 8 steps\n";
+        assert_eq!(output, expect);
+    }
+    #[test]
+    fn test_breakpoint() {
+        let source = "carry ( x )\nx = 2\nwhile x != 0 {\n  move_right\n  x = 0\n}\ny = 5";
+        let output = debug(source, &["break 5", "continue", "print x -1", "frame"]);
+        let expect = "breakpoint on line 5\n5:3 (frame 1): x = 0\nx = 0\nx = 2\ny = 0\n";
+        assert_eq!(output, expect);
+    }
+    #[test]
+    fn test_tape() {
+        let source = "carry ( x )\nx = 2\nmove_right\ny = 5";
+        let output = debug(source, &["tape on", "step 2"]);
+        let expect = [
+            "4:1 (frame 1): y = 5",
+            "  frame    x    y",
+            "      0    0    0",
+            ">     1    2    0",
+        ];
+        assert_eq!(output, expect.join("\n") + "\n");
+    }
+}
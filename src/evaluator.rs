@@ -0,0 +1,207 @@
+// Reference semantics of bfc, evaluated directly on the AST.
+//
+// Frames are maps from variable names to values, unwritten variables hold
+// zero. The tape is unbounded in both directions and frames are numbered
+// relative to the starting frame. Carried registers move their value into the
+// frame the head moves to and leave zero behind. The elements of arrays are
+// kept under their cell names, `buf[3]`; reading past the end of an array
+// yields zero and writing past it does nothing.
+
+use crate::generator::{list_arrays, list_carried};
+use crate::parser::{element_name, Bool, Direction, Statement, AST};
+use anyhow::{anyhow, Result};
+use std::collections::{BTreeMap, HashMap};
+use std::io::{Read, Write};
+
+#[derive(Debug, Default)]
+pub struct Evaluator {
+    frames: BTreeMap<i64, HashMap<String, u8>>,
+    head: i64,
+    carried: Vec<String>,
+    arrays: HashMap<String, u8>,
+    steps: u64,
+    limit: Option<u64>,
+}
+
+impl Evaluator {
+    // With a `limit`, running more statements than that is an error.
+    pub fn new(ast: &AST, limit: Option<u64>) -> Self {
+        Self {
+            carried: list_carried(ast),
+            arrays: list_arrays(ast).into_iter().collect(),
+            limit,
+            ..Self::default()
+        }
+    }
+    pub fn head(&self) -> i64 {
+        self.head
+    }
+    pub fn get(&self, frame: i64, var: &str) -> u8 {
+        self.frames
+            .get(&frame)
+            .and_then(|f| f.get(var))
+            .copied()
+            .unwrap_or(0)
+    }
+    // Frames that have been written, leftmost first.
+    pub fn frames(&self) -> impl Iterator<Item = i64> + '_ {
+        self.frames.keys().copied()
+    }
+    fn set(&mut self, frame: i64, var: &str, val: u8) {
+        self.frames
+            .entry(frame)
+            .or_default()
+            .insert(var.to_string(), val);
+    }
+    fn condition(&self, cond: &Bool) -> bool {
+        cond.compares().iter().all(|c| {
+            c.evaluate(&|var| Some(self.get(self.head, var)))
+                .unwrap_or_default()
+        })
+    }
+    fn move_head(&mut self, direction: Direction) {
+        let next = match direction {
+            Direction::Left => self.head - 1,
+            Direction::Right => self.head + 1,
+        };
+        for reg in self.carried.clone() {
+            let val = self.get(self.head, &reg);
+            self.set(self.head, &reg, 0);
+            self.set(next, &reg, val);
+        }
+        self.head = next;
+    }
+    fn count_step(&mut self) -> Result<()> {
+        self.steps += 1;
+        match self.limit {
+            Some(limit) if self.steps > limit => Err(anyhow!("Exceeded {} statements", limit)),
+            _ => Ok(()),
+        }
+    }
+    fn statements(
+        &mut self,
+        statements: &[Statement],
+        input: &mut impl Read,
+        output: &mut impl Write,
+    ) -> Result<()> {
+        for stmt in statements {
+            self.count_step()?;
+            match stmt {
+                Statement::Assign(var, val) => self.set(self.head, var, val.into()),
+                Statement::Compute(var, expr) => {
+                    let head = self.head;
+                    let val = expr.evaluate(&|name| Some(self.get(head, name)));
+                    self.set(head, var, val.unwrap_or_default());
+                }
+                Statement::Store(array, index, expr) => {
+                    let head = self.head;
+                    let value = |name: &str| Some(self.get(head, name));
+                    let index = index.evaluate(&value).unwrap_or_default();
+                    let val = expr.evaluate(&value).unwrap_or_default();
+                    if index < self.arrays.get(&**array).copied().unwrap_or(0) {
+                        self.set(head, &element_name(array, index), val);
+                    }
+                }
+                Statement::Input(var) => {
+                    let mut buf = [0];
+                    let val = match input.read(&mut buf)? {
+                        0 => 0,
+                        _ => buf[0],
+                    };
+                    self.set(self.head, var, val);
+                }
+                Statement::Output(var) => output.write_all(&[self.get(self.head, var)])?,
+                Statement::Move(direction, _) => self.move_head(*direction),
+                Statement::Carry(_) | Statement::Let(..) => {}
+                Statement::IF(cond, if_func, else_func) => {
+                    if self.condition(cond) {
+                        self.statements(if_func.statements(), input, output)?;
+                    } else if let Some(else_func) = else_func {
+                        self.statements(else_func.statements(), input, output)?;
+                    }
+                }
+                Statement::WHILE(cond, func) => {
+                    while self.condition(cond) {
+                        self.statements(func.statements(), input, output)?;
+                        self.count_step()?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+    pub fn run(&mut self, ast: &AST, input: &mut impl Read, output: &mut impl Write) -> Result<()> {
+        self.statements(ast.statements(), input, output)
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::module_inception)]
+mod evaluator {
+    use super::*;
+    use crate::scanner::TokenStream;
+
+    fn evaluate(program: &str, input: &str) -> (Vec<u8>, Evaluator) {
+        let tokens = TokenStream::try_from(program).unwrap().into_tokens();
+        let ast = AST::try_from(&*tokens).unwrap();
+        let mut evaluator = Evaluator::new(&ast, Some(1000));
+        let mut output = vec![];
+        evaluator
+            .run(&ast, &mut input.as_bytes(), &mut output)
+            .unwrap();
+        (output, evaluator)
+    }
+    #[test]
+    fn test_io() {
+        let program = "input ( c ) while c != 0 { output ( c ) input ( c ) }";
+        let (output, _) = evaluate(program, "abc");
+        assert_eq!(output, b"abc");
+    }
+    #[test]
+    fn test_division_by_zero() {
+        let program = "input ( a ) z = 0 q = a / z r = a % z s = a / 0 t = a % 0";
+        let (_, evaluator) = evaluate(program, "a");
+        assert_eq!(evaluator.get(0, "q"), 0);
+        assert_eq!(evaluator.get(0, "r"), 97);
+        assert_eq!(evaluator.get(0, "s"), 0);
+        assert_eq!(evaluator.get(0, "t"), 97);
+    }
+    #[test]
+    fn test_arrays() {
+        let program = "let t [ 3 ] i = 2 t [ i ] = 5 t [ 3 ] = 6 t [ i + 7 ] = 7
+            x = t [ 2 ] y = t [ i + 1 ] move_right t [ 0 ] = 1";
+        let (_, evaluator) = evaluate(program, "");
+        assert_eq!(evaluator.get(0, "t[2]"), 5);
+        assert_eq!(evaluator.get(0, "t[3]"), 0);
+        assert_eq!(evaluator.get(0, "x"), 5);
+        assert_eq!(evaluator.get(0, "y"), 0);
+        assert_eq!(evaluator.get(0, "t[0]"), 0);
+        assert_eq!(evaluator.get(1, "t[0]"), 1);
+    }
+    #[test]
+    fn test_frames() {
+        let program = "x = 1 move_left x = 2 move_right move_right if x == 0 { y = 3 }";
+        let (_, evaluator) = evaluate(program, "");
+        assert_eq!(evaluator.head(), 1);
+        assert_eq!(evaluator.frames().collect::<Vec<_>>(), vec![-1, 0, 1]);
+        assert_eq!(evaluator.get(-1, "x"), 2);
+        assert_eq!(evaluator.get(0, "x"), 1);
+        assert_eq!(evaluator.get(1, "y"), 3);
+    }
+    #[test]
+    fn test_carry() {
+        let program = "carry ( s ) s = 7 move_right move_right output ( s )";
+        let (output, evaluator) = evaluate(program, "");
+        assert_eq!(output, &[7]);
+        assert_eq!(evaluator.get(0, "s"), 0);
+        assert_eq!(evaluator.get(2, "s"), 7);
+    }
+    #[test]
+    fn test_limit() {
+        let tokens = TokenStream::try_from("x = 1 while x != 0 { }").unwrap();
+        let ast = AST::try_from(&*tokens.into_tokens()).unwrap();
+        let mut evaluator = Evaluator::new(&ast, Some(100));
+        let result = evaluator.run(&ast, &mut [].as_slice(), &mut vec![]);
+        assert!(result.is_err());
+    }
+}
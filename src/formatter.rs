@@ -0,0 +1,236 @@
+// Canonical formatting of bfc programs.
+//
+// Every statement goes on a line of its own, blocks are indented by two
+// spaces and tokens are separated by single spaces. Given the source the AST
+// was parsed from, a blank line before a statement is kept, so statements can
+// still be grouped; runs of blank lines collapse into one and blank lines at
+// the start of a block are dropped.
+
+use crate::parser::{Bool, Compare, Direction, Expr, Op, Statement, AST};
+use crate::scanner::Location;
+
+const INDENT: &str = "  ";
+
+fn bool_to_string(cond: &Bool) -> String {
+    cond.compares()
+        .iter()
+        .map(|c| match c {
+            Compare::EQ(var, val) => format!("{} == {}", &**var, u8::from(val)),
+            Compare::NE(var, val) => format!("{} != {}", &**var, u8::from(val)),
+            Compare::Order(var, op, right) => {
+                format!("{} {} {}", &**var, op.symbol(), expr_to_string(right))
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" && ")
+}
+
+// Operands are put in parentheses only where the precedence of the operators
+// needs them. Operators of the same precedence group to the left, and
+// comparisons do not chain.
+fn expr_to_string(expr: &Expr) -> String {
+    let (op, left, right) = match expr {
+        Expr::Num(num) => return u8::from(num).to_string(),
+        Expr::Variable(var) => return var.to_string(),
+        Expr::Element(array, index) => {
+            return format!("{} [ {} ]", &**array, expr_to_string(index))
+        }
+        Expr::Binary(op, left, right) => (op, left, right),
+    };
+    let chained = |inner: Op| inner.precedence() == 0 && op.precedence() == 0;
+    let left = match **left {
+        Expr::Binary(inner, ..) if inner.precedence() < op.precedence() || chained(inner) => {
+            format!("( {} )", expr_to_string(left))
+        }
+        _ => expr_to_string(left),
+    };
+    let right = match **right {
+        Expr::Binary(inner, ..) if inner.precedence() <= op.precedence() => {
+            format!("( {} )", expr_to_string(right))
+        }
+        _ => expr_to_string(right),
+    };
+    format!("{} {} {}", left, op.symbol(), right)
+}
+
+// The tokens of a statement before its anchor, without whitespace.
+fn lead(stmt: &Statement) -> &'static str {
+    match stmt {
+        Statement::IF(..) => "if",
+        Statement::WHILE(..) => "while",
+        Statement::Input(_) => "input(",
+        Statement::Output(_) => "output(",
+        Statement::Carry(_) => "carry(",
+        Statement::Let(..) => "let",
+        Statement::Assign(..)
+        | Statement::Compute(..)
+        | Statement::Store(..)
+        | Statement::Move(..) => "",
+    }
+}
+
+// Whether the statement starts a line of `source` that follows a blank line.
+fn follows_blank_line(stmt: &Statement, source: Option<&str>) -> bool {
+    let (Some(source), Some(anchor)) = (source, stmt.anchor()) else {
+        return false;
+    };
+    let Some(location) = Location::find(source, anchor) else {
+        return false;
+    };
+    let lines = source.lines().collect::<Vec<_>>();
+    let before = lines[location.line - 1]
+        .chars()
+        .take(location.column - 1)
+        .filter(|c| !c.is_whitespace())
+        .collect::<String>();
+    before == lead(stmt) && location.line > 1 && lines[location.line - 2].trim().is_empty()
+}
+
+struct Formatter<'s> {
+    source: Option<&'s str>,
+    output: String,
+}
+
+impl Formatter<'_> {
+    fn line(&mut self, depth: usize, text: &str) {
+        self.output.push_str(&INDENT.repeat(depth));
+        self.output.push_str(text);
+        self.output.push('\n');
+    }
+    fn block(&mut self, statements: &[Statement], depth: usize) {
+        for (i, stmt) in statements.iter().enumerate() {
+            if i > 0 && follows_blank_line(stmt, self.source) {
+                self.output.push('\n');
+            }
+            self.statement(stmt, depth);
+        }
+    }
+    fn statement(&mut self, stmt: &Statement, depth: usize) {
+        match stmt {
+            Statement::Assign(var, val) => {
+                self.line(depth, &format!("{} = {}", &**var, u8::from(val)))
+            }
+            Statement::Compute(var, expr) => {
+                self.line(depth, &format!("{} = {}", &**var, expr_to_string(expr)))
+            }
+            Statement::Store(array, index, expr) => self.line(
+                depth,
+                &format!(
+                    "{} [ {} ] = {}",
+                    &**array,
+                    expr_to_string(index),
+                    expr_to_string(expr)
+                ),
+            ),
+            Statement::Let(array, len) => {
+                self.line(depth, &format!("let {} [ {} ]", &**array, u8::from(len)))
+            }
+            Statement::Input(var) => self.line(depth, &format!("input ( {} )", &**var)),
+            Statement::Output(var) => self.line(depth, &format!("output ( {} )", &**var)),
+            Statement::Carry(var) => self.line(depth, &format!("carry ( {} )", &**var)),
+            Statement::Move(Direction::Left, _) => self.line(depth, "move_left"),
+            Statement::Move(Direction::Right, _) => self.line(depth, "move_right"),
+            Statement::WHILE(cond, func) => {
+                self.line(depth, &format!("while {} {{", bool_to_string(cond)));
+                self.block(func.statements(), depth + 1);
+                self.line(depth, "}");
+            }
+            Statement::IF(cond, if_func, else_func) => {
+                self.line(depth, &format!("if {} {{", bool_to_string(cond)));
+                self.block(if_func.statements(), depth + 1);
+                if let Some(else_func) = else_func {
+                    self.line(depth, "} else {");
+                    self.block(else_func.statements(), depth + 1);
+                }
+                self.line(depth, "}");
+            }
+        }
+    }
+}
+
+// `source` is the text `ast` was parsed from, given to keep its blank lines.
+pub fn format(ast: &AST, source: Option<&str>) -> String {
+    let mut formatter = Formatter {
+        source,
+        output: String::new(),
+    };
+    formatter.block(ast.statements(), 0);
+    formatter.output
+}
+
+#[cfg(test)]
+#[allow(clippy::module_inception)]
+mod formatter {
+    use super::*;
+    use crate::random::{random_program, Entropy};
+    use crate::scanner::TokenStream;
+
+    fn format_source(source: &str) -> String {
+        let tokens = TokenStream::try_from(source).unwrap().into_tokens();
+        let ast = AST::try_from(&*tokens).unwrap();
+        format(&ast, Some(source))
+    }
+    #[test]
+    fn test_format() {
+        let source = "carry ( s )  x   = 1\n\n\n  if x == 1 && s != 2 {\n\n output ( x ) } else { while s != 0 { input ( s ) move_left } }\nmove_right";
+        let expect = "carry ( s )\nx = 1\n\nif x == 1 && s != 2 {\n  output ( x )\n} else {\n  while s != 0 {\n    input ( s )\n    move_left\n  }\n}\nmove_right\n";
+        assert_eq!(format_source(source), expect);
+    }
+    #[test]
+    fn test_expressions() {
+        let cases = [
+            ("x = a + b * c", "x = a + b * c\n"),
+            ("x = ( a + b ) * c", "x = ( a + b ) * c\n"),
+            ("x = a - ( b - c )", "x = a - ( b - c )\n"),
+            ("x = ( a - b ) - c", "x = a - b - c\n"),
+            ("x = ( a < b ) >= ( ( c ) )", "x = ( a < b ) >= c\n"),
+            ("x = a % 2 + 1 <= 3", "x = a % 2 + 1 <= 3\n"),
+            ("let t [ 4 ]", "let t [ 4 ]\n"),
+            (
+                "t [ ( i + 1 ) ] = t [ t [ 0 ] ] * 2",
+                "t [ i + 1 ] = t [ t [ 0 ] ] * 2\n",
+            ),
+            (
+                "while a  <  b && a >= 3 { if b  >  a { } }",
+                "while a < b && a >= 3 {\n  if b > a {\n  }\n}\n",
+            ),
+        ];
+        for (source, expect) in cases {
+            assert_eq!(format_source(source), expect);
+        }
+    }
+    #[test]
+    fn test_empty_blocks() {
+        let expect = "if x == 1 {\n} else {\n}\nwhile x != 0 {\n}\n";
+        assert_eq!(
+            format_source("if x == 1 { } else { } while x != 0 { }"),
+            expect
+        );
+    }
+    #[test]
+    fn test_display() {
+        let source = "x = 1\n\ny = 2";
+        let tokens = TokenStream::try_from(source).unwrap().into_tokens();
+        let ast = AST::try_from(&*tokens).unwrap();
+        assert_eq!(ast.to_string(), "x = 1\ny = 2\n");
+    }
+    #[test]
+    fn test_round_trip() {
+        for seed in 0..50u8 {
+            let bytes = (0..128u8)
+                .map(|i| seed.wrapping_mul(31) ^ i.wrapping_mul(7))
+                .collect::<Vec<_>>();
+            let source = random_program(&mut Entropy::new(&bytes));
+            let tokens = TokenStream::try_from(source.as_str())
+                .unwrap()
+                .into_tokens();
+            let ast = AST::try_from(&*tokens).unwrap();
+            let formatted = format(&ast, Some(&source));
+            let tokens = TokenStream::try_from(formatted.as_str())
+                .unwrap()
+                .into_tokens();
+            assert_eq!(AST::try_from(&*tokens).unwrap(), ast);
+            assert_eq!(format_source(&formatted), formatted);
+        }
+    }
+}
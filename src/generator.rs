@@ -1,7 +1,10 @@
 use crate::assembler::{Value, Variable};
-use crate::parser::{self, Bool, Compare, Direction, Function, Statement, AST};
+use crate::parser::{
+    self, element_name, Bool, Compare, Direction, Expr, Function, Op, Statement, AST,
+};
+use crate::scanner::Location;
 use anyhow::{anyhow, Result};
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::fmt::Display;
 
 const TEMP_VAR: &str = "tmp";
@@ -9,7 +12,22 @@ const IF_FLAG: &str = "IF";
 const ELSE_FLAG: &str = "ELSE";
 const IS_EQ: &str = "IS_EQ";
 const WHILE_FLAG: &str = "WHILE";
-const RESERVED_VARIABLES: [&str; 5] = [TEMP_VAR, IF_FLAG, ELSE_FLAG, IS_EQ, WHILE_FLAG];
+pub const RESERVED_VARIABLES: [&str; 5] = [TEMP_VAR, IF_FLAG, ELSE_FLAG, IS_EQ, WHILE_FLAG];
+// Every other name the generator defines starts with it, so programs may not.
+const INTERNAL_PREFIX: &str = "__";
+// Temporaries of expressions, `__expr0` and up. Like TEMP_VAR they hold zero
+// between statements.
+const EXPR_PREFIX: &str = "__expr";
+const CELL_SIZE: &str = "__cell_size";
+const NEXT_FRAME_PREFIX: &str = "__next_";
+// TEMP_VAR is only used inside a single `copy` pair and is zero between any
+// two instructions, so the assembler may use it as scratch space.
+const SCRATCH: &str = "__scratch";
+// Not used by the assembler, records the frame the program starts in for tools
+// that read the tape.
+const TAPE_ORIGIN: &str = "__tape_origin";
+// Cells that walk along an array to the element at a variable index.
+const WALKERS: usize = 4;
 
 #[derive(Debug, PartialEq, Clone)]
 pub enum Asm {
@@ -24,6 +42,10 @@ pub enum Asm {
     Copy(Variable, Vec<Variable>),
     Read(Variable),
     Write(Variable),
+    // Enclose the code of the statement at the location, for tracing
+    // Brainfuck back to the source.
+    StmtBegin(Location),
+    StmtEnd,
 }
 
 impl Display for Asm {
@@ -48,33 +70,116 @@ impl Display for Asm {
             }
             Read(var) => write!(f, "read {}", var),
             Write(var) => write!(f, "write {}", var),
+            StmtBegin(location) => write!(f, "# stmt {}", location),
+            StmtEnd => write!(f, "# end stmt"),
         }
     }
 }
 
 impl From<&AST<'_>> for Vec<Asm> {
     fn from(ast: &AST) -> Self {
-        statements_to_asm(ast.statements())
+        generate(ast, None)
     }
 }
 
-fn statements_to_asm(statements: &[Statement]) -> Vec<Asm> {
-    statements.iter().flat_map(Vec::<Asm>::from).collect()
+// With the `source` the AST was parsed from, the code of every statement is
+// enclosed in `StmtBegin` and `StmtEnd`.
+fn generate(ast: &AST, source: Option<&str>) -> Vec<Asm> {
+    let asm = statements_to_asm(ast.statements(), source);
+    carry_registers(asm, &list_carried(ast))
 }
 
-fn generate_set_ne(var: &parser::Variable, val: &parser::Num, flag: &str) -> Vec<Asm> {
-    let flag = Variable::new(flag);
-    let val = Value::new_num(val.into());
+// The same variable of the neighbouring frame on the right, i.e. `var + __cell_size`.
+fn next_frame(var: &str) -> Variable {
+    Variable::new(&format!("{}{}", NEXT_FRAME_PREFIX, var))
+}
+
+// Rewrite every frame move so that the carried registers travel with the head.
+// Moving right drains each register into the next frame before shifting,
+// moving left shifts first and then drains the old frame (now the next one)
+// back into the current frame.
+fn carry_registers(asm: Vec<Asm>, registers: &[String]) -> Vec<Asm> {
+    if registers.is_empty() {
+        return asm;
+    }
+    let cell_size = Value::new_const(CELL_SIZE);
+    asm.into_iter()
+        .flat_map(|a| match a {
+            Asm::Rs(ref val) if *val == cell_size => registers
+                .iter()
+                .map(|reg| Asm::Copy(Variable::new(reg), vec![next_frame(reg)]))
+                .chain(std::iter::once(a.clone()))
+                .collect(),
+            Asm::Ls(ref val) if *val == cell_size => std::iter::once(a.clone())
+                .chain(
+                    registers
+                        .iter()
+                        .map(|reg| Asm::Copy(next_frame(reg), vec![Variable::new(reg)])),
+                )
+                .collect(),
+            a => vec![a],
+        })
+        .collect()
+}
+
+fn statements_to_asm(statements: &[Statement], source: Option<&str>) -> Vec<Asm> {
+    statements
+        .iter()
+        .flat_map(|stmt| {
+            let asm = statement_to_asm(stmt, source);
+            let location = source
+                .zip(stmt.anchor())
+                .and_then(|(source, anchor)| Location::find(source, anchor));
+            match location {
+                Some(location) if !asm.is_empty() => {
+                    [vec![Asm::StmtBegin(location)], asm, vec![Asm::StmtEnd]].concat()
+                }
+                _ => asm,
+            }
+        })
+        .collect()
+}
+
+// Copy `var` into the cell `dest`, keeping `var`.
+fn generate_load(var: &str, dest: &str) -> Vec<Asm> {
     vec![
         Asm::Copy(
             Variable::new(var),
-            vec![Variable::new(TEMP_VAR), flag.clone()],
+            vec![Variable::new(TEMP_VAR), Variable::new(dest)],
         ),
         Asm::Copy(Variable::new(TEMP_VAR), vec![Variable::new(var)]),
-        Asm::Sub(flag.clone(), val.clone()),
     ]
 }
 
+fn generate_set_ne(var: &parser::Variable, val: &parser::Num, flag: &str) -> Vec<Asm> {
+    let val = Value::new_num(val.into());
+    [
+        generate_load(var, flag),
+        vec![Asm::Sub(Variable::new(flag), val)],
+    ]
+    .concat()
+}
+
+// An ordered comparison as the expression evaluating it to 1 or 0.
+fn order_expr<'a>(var: &parser::Variable<'a>, op: Op, right: &Expr<'a>) -> Expr<'a> {
+    Expr::Binary(
+        op,
+        Box::new(Expr::Variable(var.clone())),
+        Box::new(right.clone()),
+    )
+}
+
+fn generate_set_order(var: &parser::Variable, op: Op, right: &Expr, flag: &str) -> Vec<Asm> {
+    [
+        generate_expr(&order_expr(var, op, right), 0),
+        vec![Asm::Copy(
+            Variable::new(&temp(0)),
+            vec![Variable::new(flag)],
+        )],
+    ]
+    .concat()
+}
+
 fn generate_if_flag(flag: &str, func: Vec<Asm>, set: Vec<(&str, u8)>) -> Vec<Asm> {
     let flag = Variable::new(flag);
     [
@@ -112,6 +217,11 @@ fn generate_if(conditions: &[Compare], func_if: Vec<Asm>, flag: Vec<(&str, u8)>)
             generate_if_flag(IS_EQ, func_if, flag),
         ]
         .concat(),
+        [Compare::Order(var, op, right)] => [
+            generate_set_order(var, *op, right, IF_FLAG),
+            generate_if_flag(IF_FLAG, func_if, flag),
+        ]
+        .concat(),
         [Compare::NE(var, val), rest @ ..] => [
             generate_set_ne(var, val, IF_FLAG),
             generate_if_flag(IF_FLAG, generate_if(rest, func_if, flag), vec![]),
@@ -124,17 +234,109 @@ fn generate_if(conditions: &[Compare], func_if: Vec<Asm>, flag: Vec<(&str, u8)>)
             generate_if_flag(IS_EQ, generate_if(rest, func_if, flag), vec![]),
         ]
         .concat(),
+        [Compare::Order(var, op, right), rest @ ..] => [
+            generate_set_order(var, *op, right, IF_FLAG),
+            generate_if_flag(IF_FLAG, generate_if(rest, func_if, flag), vec![]),
+        ]
+        .concat(),
         [] => vec![],
     }
 }
 
+// Collect `if x == a { A } else { if x == b { B } else { D } }` into the arms
+// `[(a, A), (b, B)]` and the default `D`.
+fn collect_arms<'a, 'b>(
+    var: &parser::Variable,
+    func_else: &'b Option<Function<'a>>,
+    arms: &mut Vec<(u8, &'b Function<'a>)>,
+) -> Option<&'b Function<'a>> {
+    match func_else.as_ref().map(|f| f.statements()) {
+        Some([Statement::IF(cond, next_if, next_else)]) => match cond.compares() {
+            [Compare::EQ(next_var, val)] if **next_var == **var => {
+                arms.push((val.into(), next_if));
+                collect_arms(var, next_else, arms)
+            }
+            _ => func_else.as_ref(),
+        },
+        _ => func_else.as_ref(),
+    }
+}
+
+// Only the first arm is the `if` branch, so only it sets `flag`.
+fn generate_arms(
+    arms: &[(u8, Vec<Asm>)],
+    previous: u8,
+    default: Vec<Asm>,
+    flag: Vec<(&str, u8)>,
+    nested: bool,
+) -> Vec<Asm> {
+    let [(val, func), rest @ ..] = arms else {
+        return vec![];
+    };
+    let miss = if rest.is_empty() {
+        [default, generate_flag_setup(vec![(IS_EQ, 0), (IF_FLAG, 0)])].concat()
+    } else {
+        generate_arms(rest, *val, default, vec![], true)
+    };
+    let hit_flag = if nested {
+        [flag, vec![(IF_FLAG, 0)]].concat()
+    } else {
+        flag
+    };
+    let diff = val.wrapping_sub(previous);
+    [
+        if nested && diff != 0 {
+            vec![Asm::Sub(Variable::new(IF_FLAG), Value::new_num(diff))]
+        } else {
+            vec![]
+        },
+        vec![Asm::Loop(Variable::new(IF_FLAG))],
+        miss,
+        vec![Asm::End(Variable::new(IF_FLAG))],
+        generate_if_flag(IS_EQ, func.clone(), hit_flag),
+    ]
+    .concat()
+}
+
+// Equality chains on a single variable are lowered as one decrement chain:
+// IF holds `x - a`, each arm subtracts the distance to its own value and only
+// descends into the next arm while IF is non-zero. IS_EQ survives only on the
+// way out of the arm that matched, and the innermost arm runs the default.
+// Every arm resets IS_EQ and IF itself so the checks on the way out still see
+// zeros after an arm moved the head to another frame.
+fn generate_switch(
+    var: &parser::Variable,
+    arms: &[(u8, Vec<Asm>)],
+    default: Vec<Asm>,
+    flag: Vec<(&str, u8)>,
+) -> Vec<Asm> {
+    let first = parser::Num::from(arms[0].0);
+    [
+        generate_flag_setup(vec![(IS_EQ, 1)]),
+        generate_set_ne(var, &first, IF_FLAG),
+        generate_arms(arms, arms[0].0, default, flag, false),
+    ]
+    .concat()
+}
+
 fn generate_if_else(
     condition: &Bool,
     func_if: &Function,
     func_else: &Option<Function>,
     flag: Vec<(&str, u8)>,
+    source: Option<&str>,
 ) -> Vec<Asm> {
-    let func_if = statements_to_asm(func_if.statements());
+    if let [Compare::EQ(var, val)] = condition.compares() {
+        let mut arms = vec![(val.into(), func_if)];
+        let default = collect_arms(var, func_else, &mut arms);
+        let arms = arms
+            .into_iter()
+            .map(|(val, func)| (val, statements_to_asm(func.statements(), source)))
+            .collect::<Vec<_>>();
+        let default = default.map_or(vec![], |f| statements_to_asm(f.statements(), source));
+        return generate_switch(var, &arms, default, flag);
+    }
+    let func_if = statements_to_asm(func_if.statements(), source);
     let flag = if func_else.is_some() {
         [flag, vec![(ELSE_FLAG, 0)]].concat()
     } else {
@@ -147,7 +349,7 @@ fn generate_if_else(
     };
     let if_asm = generate_if(condition.compares(), func_if, flag);
     let else_asm = if let Some(func_else) = func_else {
-        let else_asm = statements_to_asm(func_else.statements());
+        let else_asm = statements_to_asm(func_else.statements(), source);
         generate_if_flag(ELSE_FLAG, else_asm, vec![])
     } else {
         vec![]
@@ -155,34 +357,473 @@ fn generate_if_else(
     [setup_asm, if_asm, else_asm].concat()
 }
 
-fn generate_while(condition: &Bool, func: &Function) -> Vec<Asm> {
+fn temp(index: usize) -> String {
+    format!("{}{}", EXPR_PREFIX, index)
+}
+
+// Run `body` as many times as `cell` counts, leaving it zero.
+fn generate_drain(cell: &str, body: Vec<Asm>) -> Vec<Asm> {
+    let cell = Variable::new(cell);
+    [
+        vec![
+            Asm::Loop(cell.clone()),
+            Asm::Sub(cell.clone(), Value::new_num(1)),
+        ],
+        body,
+        vec![Asm::End(cell)],
+    ]
+    .concat()
+}
+
+// a = a * b: add b to a once for every unit of a, restoring b from `spare`
+// after each addition.
+fn generate_multiply(a: &str, b: &str, count: &str, spare: &str) -> Vec<Asm> {
+    let one = Value::new_num(1);
+    let add = generate_drain(
+        b,
+        vec![
+            Asm::Add(Variable::new(a), one.clone()),
+            Asm::Add(Variable::new(spare), one),
+        ],
+    );
+    [
+        vec![Asm::Copy(Variable::new(a), vec![Variable::new(count)])],
+        generate_drain(
+            count,
+            [
+                add,
+                vec![Asm::Copy(Variable::new(spare), vec![Variable::new(b)])],
+            ]
+            .concat(),
+        ),
+        vec![Asm::Set(Variable::new(b), Value::new_num(0))],
+    ]
+    .concat()
+}
+
+// a = a / b or a % b: count a down into the remainder and whenever the
+// remainder reaches b, count the quotient up and start the remainder over.
+// The remainder never reaches a divisor of 0, so a / 0 is 0 and a % 0 is a.
+fn generate_divmod(op: Op, a: &str, b: &str, scratch: [&str; 5]) -> Vec<Asm> {
+    let [quotient, remainder, diff, spare, equal] = scratch;
+    let one = Value::new_num(1);
+    let step = [
+        vec![
+            Asm::Add(Variable::new(remainder), one.clone()),
+            Asm::Copy(
+                Variable::new(b),
+                vec![Variable::new(diff), Variable::new(spare)],
+            ),
+            Asm::Copy(Variable::new(spare), vec![Variable::new(b)]),
+            Asm::Copy(Variable::new(remainder), vec![Variable::new(spare)]),
+        ],
+        // diff = b - remainder, keeping the remainder.
+        generate_drain(
+            spare,
+            vec![
+                Asm::Sub(Variable::new(diff), one.clone()),
+                Asm::Add(Variable::new(remainder), one.clone()),
+            ],
+        ),
+        generate_flag_setup(vec![(equal, 1)]),
+        generate_if_flag(diff, vec![], vec![(equal, 0)]),
+        generate_if_flag(
+            equal,
+            vec![Asm::Add(Variable::new(quotient), one)],
+            vec![(remainder, 0)],
+        ),
+    ]
+    .concat();
+    let (result, other) = if op == Op::DIV {
+        (quotient, remainder)
+    } else {
+        (remainder, quotient)
+    };
+    [
+        generate_drain(a, step),
+        generate_flag_setup(vec![(b, 0), (other, 0)]),
+        vec![Asm::Copy(Variable::new(result), vec![Variable::new(a)])],
+    ]
+    .concat()
+}
+
+// less = a < b: count both down until one of them runs out, a runs out first
+// exactly when it is the smaller one. Leaves a and b zero.
+fn generate_less(a: &str, b: &str, less: &str, scratch: [&str; 2]) -> Vec<Asm> {
+    let [ran_out, rest] = scratch;
+    let step = [
+        generate_flag_setup(vec![(ran_out, 1)]),
+        vec![Asm::Copy(Variable::new(a), vec![Variable::new(rest)])],
+        generate_if_flag(
+            rest,
+            vec![
+                Asm::Sub(Variable::new(rest), Value::new_num(1)),
+                Asm::Copy(Variable::new(rest), vec![Variable::new(a)]),
+            ],
+            vec![(ran_out, 0)],
+        ),
+        generate_if_flag(ran_out, vec![], vec![(less, 1), (b, 0)]),
+    ]
+    .concat();
+    [generate_drain(b, step), generate_flag_setup(vec![(a, 0)])].concat()
+}
+
+// Temporaries `generate_op` needs besides its two operands.
+fn op_temps(op: Op) -> usize {
+    match op {
+        Op::ADD | Op::SUB => 0,
+        Op::MUL => 2,
+        Op::DIV | Op::MOD => 5,
+        Op::LT | Op::LE | Op::GT | Op::GE => 3,
+    }
+}
+
+// Apply `op` to the operands in the temporaries `base` and `base + 1`,
+// leaving the result in the first one and zero in the others.
+fn generate_op(op: Op, base: usize) -> Vec<Asm> {
+    let names = (base..base + 2 + op_temps(op))
+        .map(temp)
+        .collect::<Vec<_>>();
+    let names = names.iter().map(String::as_str).collect::<Vec<_>>();
+    let (a, b) = (names[0], names[1]);
+    let one = Value::new_num(1);
+    match op {
+        Op::ADD => generate_drain(b, vec![Asm::Add(Variable::new(a), one)]),
+        Op::SUB => generate_drain(b, vec![Asm::Sub(Variable::new(a), one)]),
+        Op::MUL => generate_multiply(a, b, names[2], names[3]),
+        Op::DIV | Op::MOD => {
+            generate_divmod(op, a, b, [names[2], names[3], names[4], names[5], names[6]])
+        }
+        Op::LT | Op::GT | Op::LE | Op::GE => {
+            let (left, right) = if matches!(op, Op::LT | Op::GE) {
+                (a, b)
+            } else {
+                (b, a)
+            };
+            let less = generate_less(left, right, names[2], [names[3], names[4]]);
+            // a <= b is !(b < a) and a >= b is !(a < b).
+            let result = if matches!(op, Op::LT | Op::GT) {
+                vec![Asm::Copy(Variable::new(names[2]), vec![Variable::new(a)])]
+            } else {
+                [
+                    generate_flag_setup(vec![(a, 1)]),
+                    generate_if_flag(names[2], vec![], vec![(a, 0)]),
+                ]
+                .concat()
+            };
+            [less, result].concat()
+        }
+    }
+}
+
+// a = a / n or a % n for a constant n: like `generate_divmod`, but the
+// remainder is compared against n directly instead of a copy of the divisor.
+fn generate_divmod_by(op: Op, a: &str, n: u8, scratch: [&str; 4]) -> Vec<Asm> {
+    let [quotient, remainder, diff, equal] = scratch;
+    let one = Value::new_num(1);
+    let step = [
+        vec![Asm::Add(Variable::new(remainder), one.clone())],
+        generate_load(remainder, diff),
+        vec![Asm::Sub(Variable::new(diff), Value::new_num(n))],
+        generate_flag_setup(vec![(equal, 1)]),
+        generate_if_flag(diff, vec![], vec![(equal, 0)]),
+        generate_if_flag(
+            equal,
+            vec![Asm::Add(Variable::new(quotient), one)],
+            vec![(remainder, 0)],
+        ),
+    ]
+    .concat();
+    let (result, other) = if op == Op::DIV {
+        (quotient, remainder)
+    } else {
+        (remainder, quotient)
+    };
+    [
+        generate_drain(a, step),
+        generate_flag_setup(vec![(other, 0)]),
+        vec![Asm::Copy(Variable::new(result), vec![Variable::new(a)])],
+    ]
+    .concat()
+}
+
+// The operand and the constant of an arithmetic operation with a constant
+// operand, which is lowered without loading the constant into a temporary.
+// Only `+` and `*` take the constant on either side.
+fn const_operand<'e, 'a>(
+    op: Op,
+    left: &'e Expr<'a>,
+    right: &'e Expr<'a>,
+) -> Option<(&'e Expr<'a>, u8)> {
+    match (op, left, right) {
+        (Op::ADD | Op::SUB | Op::MUL | Op::DIV | Op::MOD, _, Expr::Num(n)) => {
+            Some((left, n.into()))
+        }
+        (Op::ADD | Op::MUL, Expr::Num(n), _) => Some((right, n.into())),
+        _ => None,
+    }
+}
+
+// Temporaries `generate_const_op` needs besides its operand.
+fn const_op_temps(op: Op) -> usize {
+    match op {
+        Op::MUL => 1,
+        Op::DIV | Op::MOD => 4,
+        _ => 0,
+    }
+}
+
+// Apply `op` with the constant `n` to the operand in the temporary `base`.
+// Division by 0 yields 0 and the remainder of it is the operand, as in
+// `generate_divmod`.
+fn generate_const_op(op: Op, base: usize, n: u8) -> Vec<Asm> {
+    let names = (base..base + 1 + const_op_temps(op))
+        .map(temp)
+        .collect::<Vec<_>>();
+    let names = names.iter().map(String::as_str).collect::<Vec<_>>();
+    let a = Variable::new(names[0]);
+    match (op, n) {
+        (Op::ADD, _) => vec![Asm::Add(a, Value::new_num(n))],
+        (Op::SUB, _) => vec![Asm::Sub(a, Value::new_num(n))],
+        (Op::MUL, 0) | (Op::DIV, 0) | (Op::MOD, 1) => vec![Asm::Set(a, Value::new_num(0))],
+        (Op::MUL, 1) | (Op::DIV, 1) | (Op::MOD, 0) => vec![],
+        (Op::MUL, _) => [
+            vec![Asm::Copy(a.clone(), vec![Variable::new(names[1])])],
+            generate_drain(names[1], vec![Asm::Add(a, Value::new_num(n))]),
+        ]
+        .concat(),
+        _ => generate_divmod_by(op, names[0], n, [names[1], names[2], names[3], names[4]]),
+    }
+}
+
+// `var = var + n` and `var = var - n` change the variable in place.
+fn in_place(var: &parser::Variable, expr: &Expr) -> Option<Asm> {
+    let Expr::Binary(op @ (Op::ADD | Op::SUB), left, right) = expr else {
+        return None;
+    };
+    match const_operand(*op, left, right)? {
+        (Expr::Variable(operand), n) if **operand == **var => Some(if *op == Op::ADD {
+            Asm::Add(Variable::new(var), Value::new_num(n))
+        } else {
+            Asm::Sub(Variable::new(var), Value::new_num(n))
+        }),
+        _ => None,
+    }
+}
+
+// The cells after the elements of `array`, which walk along it.
+fn walker(array: &str, index: usize) -> Variable {
+    Variable::new(&format!("__{}_walk{}", array, index))
+}
+
+// The number of elements of `array`, defined with the layout.
+fn array_len(array: &str) -> Value {
+    Value::new_const(&format!("__{}_len", array))
+}
+
+// Move the head to the element of `array` whose index is in the first walker,
+// run `at` with the element at `array[0]`, and move the head back. Every step
+// shifts the walkers one cell along: the first one counts down the steps left,
+// the second one counts the steps taken and the third one carries a value
+// there or back. They leave zero behind as they move onto the runway of zero
+// cells after them, which is one cell shorter than the array.
+fn generate_walk(array: &str, at: Vec<Asm>) -> Vec<Asm> {
+    let [w0, w1, w2, w3] = [0, 1, 2, 3].map(|i| walker(array, i));
+    let one = Value::new_num(1);
+    [
+        vec![
+            Asm::Loop(w0.clone()),
+            Asm::Copy(w2.clone(), vec![w3.clone()]),
+            Asm::Copy(w1.clone(), vec![w2.clone()]),
+            Asm::Copy(w0.clone(), vec![w1.clone()]),
+            Asm::Sub(w1.clone(), one.clone()),
+            Asm::Rs(one.clone()),
+            Asm::End(w0),
+        ],
+        at,
+        vec![
+            Asm::Loop(w1.clone()),
+            Asm::Ls(one.clone()),
+            Asm::Copy(w2.clone(), vec![w1.clone()]),
+            Asm::Copy(w3, vec![w2]),
+            Asm::Sub(w1.clone(), one),
+            Asm::End(w1),
+        ],
+    ]
+    .concat()
+}
+
+// Evaluate `index` into the temporary `base` and start the walkers there when
+// it is within `array`, leaving whether it is in the temporary `base + 1`.
+fn generate_bounds(array: &str, index: &Expr, base: usize) -> Vec<Asm> {
+    [
+        generate_expr(index, base),
+        vec![
+            Asm::Copy(
+                Variable::new(&temp(base)),
+                vec![
+                    walker(array, 0),
+                    walker(array, 1),
+                    Variable::new(&temp(base + 1)),
+                ],
+            ),
+            Asm::Set(Variable::new(&temp(base + 2)), array_len(array)),
+        ],
+        generate_op(Op::LT, base + 1),
+    ]
+    .concat()
+}
+
+// The walkers keep what they were given when the index is past the end.
+fn clear_walkers(array: &str, count: usize) -> Vec<Asm> {
+    (0..count)
+        .map(|i| Asm::Set(walker(array, i), Value::new_num(0)))
+        .collect()
+}
+
+// Read the element of `array` at `index` into the temporary `base`. An index
+// past the end reads zero.
+fn generate_element(array: &str, index: &Expr, base: usize) -> Vec<Asm> {
+    if let Expr::Num(num) = index {
+        return generate_load(&element_name(array, num.into()), &temp(base));
+    }
+    let (w2, w3) = (walker(array, 2), walker(array, 3));
+    let first = Variable::new(&element_name(array, 0));
+    let pick = vec![
+        Asm::Copy(first.clone(), vec![w2.clone(), w3.clone()]),
+        Asm::Copy(w3, vec![first]),
+    ];
+    let read = [
+        generate_walk(array, pick),
+        vec![Asm::Copy(w2, vec![Variable::new(&temp(base))])],
+    ]
+    .concat();
+    [
+        generate_bounds(array, index, base),
+        generate_if_flag(&temp(base + 1), read, vec![]),
+        clear_walkers(array, 2),
+    ]
+    .concat()
+}
+
+// Temporaries `generate_element` uses.
+fn element_temps(index: &Expr) -> usize {
+    match index {
+        Expr::Num(_) => 1,
+        _ => expr_temps(index).max(3 + op_temps(Op::LT)),
+    }
+}
+
+// Write `expr` to the element of `array` at `index`. An index past the end
+// writes nothing.
+fn generate_store(array: &str, index: &Expr, expr: &Expr) -> Vec<Asm> {
+    let value = generate_expr(expr, 0);
+    if let Expr::Num(num) = index {
+        let element = Variable::new(&element_name(array, num.into()));
+        return [
+            value,
+            vec![Asm::Copy(Variable::new(&temp(0)), vec![element])],
+        ]
+        .concat();
+    }
+    let w2 = walker(array, 2);
+    let write = vec![Asm::Copy(
+        w2.clone(),
+        vec![Variable::new(&element_name(array, 0))],
+    )];
+    [
+        value,
+        generate_bounds(array, index, 1),
+        vec![Asm::Copy(Variable::new(&temp(0)), vec![w2])],
+        generate_if_flag(&temp(2), generate_walk(array, write), vec![]),
+        clear_walkers(array, 3),
+    ]
+    .concat()
+}
+
+// Temporaries `generate_store` uses.
+fn store_temps(index: &Expr, expr: &Expr) -> usize {
+    match index {
+        Expr::Num(_) => expr_temps(expr),
+        _ => expr_temps(expr).max(1 + element_temps(index)),
+    }
+}
+
+// Evaluate `expr` into the temporary `base`, using the ones above it as well.
+fn generate_expr(expr: &Expr, base: usize) -> Vec<Asm> {
+    match expr {
+        Expr::Num(num) => vec![Asm::Set(
+            Variable::new(&temp(base)),
+            Value::new_num(num.into()),
+        )],
+        Expr::Variable(var) => generate_load(var, &temp(base)),
+        Expr::Element(array, index) => generate_element(array, index, base),
+        Expr::Binary(op, left, right) => match const_operand(*op, left, right) {
+            Some((operand, n)) => [
+                generate_expr(operand, base),
+                generate_const_op(*op, base, n),
+            ]
+            .concat(),
+            None => [
+                generate_expr(left, base),
+                generate_expr(right, base + 1),
+                generate_op(*op, base),
+            ]
+            .concat(),
+        },
+    }
+}
+
+// Temporaries `generate_expr` uses.
+fn expr_temps(expr: &Expr) -> usize {
+    match expr {
+        Expr::Num(_) | Expr::Variable(_) => 1,
+        Expr::Element(_, index) => element_temps(index),
+        Expr::Binary(op, left, right) => match const_operand(*op, left, right) {
+            Some((operand, _)) => expr_temps(operand).max(1 + const_op_temps(*op)),
+            None => expr_temps(left)
+                .max(1 + expr_temps(right))
+                .max(2 + op_temps(*op)),
+        },
+    }
+}
+
+fn generate_while(condition: &Bool, func: &Function, source: Option<&str>) -> Vec<Asm> {
     [
         vec![
             Asm::Set(Variable::new(WHILE_FLAG), Value::new_num(1)),
             Asm::Loop(Variable::new(WHILE_FLAG)),
             Asm::Set(Variable::new(WHILE_FLAG), Value::new_num(0)),
         ],
-        generate_if_else(condition, func, &None, vec![(WHILE_FLAG, 1)]),
+        generate_if_else(condition, func, &None, vec![(WHILE_FLAG, 1)], source),
         vec![Asm::End(Variable::new(WHILE_FLAG))],
     ]
     .concat()
 }
-impl From<&Statement<'_>> for Vec<Asm> {
-    fn from(stmt: &Statement) -> Self {
-        match stmt {
-            Statement::Input(var) => vec![Asm::Read(Variable::new(var))],
-            Statement::Output(var) => vec![Asm::Write(Variable::new(var))],
-            Statement::Assign(var, val) => {
-                vec![Asm::Set(Variable::new(var), Value::new_num(val.into()))]
-            }
-            Statement::Move(direction) => match direction {
-                Direction::Right => vec![Asm::Rs(Value::new_const("__cell_size"))],
-                Direction::Left => vec![Asm::Ls(Value::new_const("__cell_size"))],
-            },
-            Statement::WHILE(condition, func) => generate_while(condition, func),
-            Statement::IF(condition, func_if, func_else) => {
-                generate_if_else(condition, func_if, func_else, vec![])
-            }
+fn statement_to_asm(stmt: &Statement, source: Option<&str>) -> Vec<Asm> {
+    match stmt {
+        Statement::Input(var) => vec![Asm::Read(Variable::new(var))],
+        Statement::Output(var) => vec![Asm::Write(Variable::new(var))],
+        Statement::Assign(var, val) => {
+            vec![Asm::Set(Variable::new(var), Value::new_num(val.into()))]
+        }
+        Statement::Compute(var, expr) => match in_place(var, expr) {
+            Some(asm) => vec![asm],
+            None => [
+                generate_expr(expr, 0),
+                vec![Asm::Copy(Variable::new(&temp(0)), vec![Variable::new(var)])],
+            ]
+            .concat(),
+        },
+        Statement::Move(direction, _) => match direction {
+            Direction::Right => vec![Asm::Rs(Value::new_const(CELL_SIZE))],
+            Direction::Left => vec![Asm::Ls(Value::new_const(CELL_SIZE))],
+        },
+        Statement::Store(array, index, expr) => generate_store(array, index, expr),
+        Statement::Carry(_) | Statement::Let(..) => vec![],
+        Statement::WHILE(condition, func) => generate_while(condition, func, source),
+        Statement::IF(condition, func_if, func_else) => {
+            generate_if_else(condition, func_if, func_else, vec![], source)
         }
     }
 }
@@ -190,75 +831,311 @@ impl From<&Statement<'_>> for Vec<Asm> {
 fn list_variables_bool(b: &Bool) -> HashSet<String> {
     b.compares()
         .iter()
-        .map(|c| match c {
-            Compare::EQ(var, _) => var.to_string(),
-            Compare::NE(var, _) => var.to_string(),
-        })
+        .flat_map(|c| c.variables())
+        .map(|var| var.to_string())
         .collect()
 }
 
-fn list_variables_statement(
-    stmt: &Statement,
-    if_level: usize,
-    while_level: usize,
-) -> HashSet<String> {
+fn list_variables_statement(stmt: &Statement) -> HashSet<String> {
     match stmt {
         Statement::Input(var) => HashSet::from([var.to_string()]),
         Statement::Output(var) => HashSet::from([var.to_string()]),
         Statement::Assign(var, _) => HashSet::from([var.to_string()]),
+        Statement::Compute(var, expr) => std::iter::once(var)
+            .chain(expr.variables())
+            .map(|var| var.to_string())
+            .collect(),
+        Statement::Store(_, index, expr) => index
+            .variables()
+            .into_iter()
+            .chain(expr.variables())
+            .map(|var| var.to_string())
+            .collect(),
+        Statement::Carry(var) => HashSet::from([var.to_string()]),
         Statement::WHILE(cond, stmt) => {
             let mut variables = list_variables_bool(cond);
             for stmt in stmt.statements() {
-                variables.extend(list_variables_statement(stmt, if_level, while_level + 1))
+                variables.extend(list_variables_statement(stmt))
             }
             variables
         }
         Statement::IF(cond, if_func, else_func) => {
             let mut variables = list_variables_bool(cond);
             for stmt in if_func.statements() {
-                variables.extend(list_variables_statement(stmt, if_level + 1, while_level))
+                variables.extend(list_variables_statement(stmt))
             }
             if let Some(else_func) = else_func {
                 for stmt in else_func.statements() {
-                    variables.extend(list_variables_statement(stmt, if_level, while_level));
+                    variables.extend(list_variables_statement(stmt));
                 }
             }
             variables
         }
-        Statement::Move(_) => HashSet::new(),
+        Statement::Move(..) | Statement::Let(..) => HashSet::new(),
     }
 }
 
-fn list_variables(ast: &AST) -> HashSet<String> {
+// Arrays are not variables, they are listed by `list_arrays`.
+pub fn list_variables(ast: &AST) -> HashSet<String> {
     let mut variables = HashSet::new();
     for stmt in ast.statements() {
-        variables.extend(list_variables_statement(stmt, 0, 0));
+        variables.extend(list_variables_statement(stmt));
     }
     variables
 }
 
+fn list_carried_statements(statements: &[Statement], carried: &mut Vec<String>) {
+    for stmt in statements {
+        match stmt {
+            Statement::Carry(var) if !carried.iter().any(|c| c == &**var) => {
+                carried.push(var.to_string());
+            }
+            Statement::WHILE(_, func) => list_carried_statements(func.statements(), carried),
+            Statement::IF(_, if_func, else_func) => {
+                list_carried_statements(if_func.statements(), carried);
+                if let Some(else_func) = else_func {
+                    list_carried_statements(else_func.statements(), carried);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+// Carry declarations apply to the whole program regardless of where they appear.
+pub fn list_carried(ast: &AST) -> Vec<String> {
+    let mut carried = vec![];
+    list_carried_statements(ast.statements(), &mut carried);
+    carried
+}
+
+fn list_declarations<'s, 'a>(statements: &'s [Statement<'a>], lets: &mut Vec<(&'s str, u8)>) {
+    for stmt in statements {
+        match stmt {
+            Statement::Let(array, len) => lets.push((array, len.into())),
+            Statement::WHILE(_, func) => list_declarations(func.statements(), lets),
+            Statement::IF(_, if_func, else_func) => {
+                list_declarations(if_func.statements(), lets);
+                if let Some(else_func) = else_func {
+                    list_declarations(else_func.statements(), lets);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+// The declared arrays with their lengths, in the order of their first
+// declaration. Like carry declarations they apply to the whole program.
+pub fn list_arrays(ast: &AST) -> Vec<(String, u8)> {
+    let mut lets = vec![];
+    list_declarations(ast.statements(), &mut lets);
+    let mut arrays: Vec<(String, u8)> = vec![];
+    for (array, len) in lets {
+        if !arrays.iter().any(|(a, _)| a == array) {
+            arrays.push((array.to_string(), len));
+        }
+    }
+    arrays
+}
+
+// The elements read or written by the statements, as arrays and indexes.
+fn list_elements<'s, 'a>(
+    statements: &'s [Statement<'a>],
+    elements: &mut Vec<(&'s parser::Variable<'a>, &'s Expr<'a>)>,
+) {
+    fn of_expr<'s, 'a>(
+        expr: &'s Expr<'a>,
+        elements: &mut Vec<(&'s parser::Variable<'a>, &'s Expr<'a>)>,
+    ) {
+        match expr {
+            Expr::Num(_) | Expr::Variable(_) => {}
+            Expr::Element(array, index) => {
+                elements.push((array, index));
+                of_expr(index, elements);
+            }
+            Expr::Binary(_, left, right) => {
+                of_expr(left, elements);
+                of_expr(right, elements);
+            }
+        }
+    }
+    for stmt in statements {
+        match stmt {
+            Statement::Compute(_, expr) => of_expr(expr, elements),
+            Statement::Store(array, index, expr) => {
+                elements.push((array, index));
+                of_expr(index, elements);
+                of_expr(expr, elements);
+            }
+            Statement::WHILE(_, func) => list_elements(func.statements(), elements),
+            Statement::IF(_, if_func, else_func) => {
+                list_elements(if_func.statements(), elements);
+                if let Some(else_func) = else_func {
+                    list_elements(else_func.statements(), elements);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+// Every array is declared once with a single length, is not also a variable,
+// and is not indexed past its end by a constant.
+fn check_arrays(ast: &AST, variables: &HashSet<String>) -> Result<Vec<(String, u8)>> {
+    let mut lets = vec![];
+    list_declarations(ast.statements(), &mut lets);
+    let arrays = list_arrays(ast);
+    let lengths = arrays
+        .iter()
+        .map(|(array, len)| (array.as_str(), *len))
+        .collect::<HashMap<_, _>>();
+    for (array, len) in lets {
+        if lengths[array] != len {
+            return Err(anyhow!(
+                "Array `{}` is declared with {} and {} elements",
+                array,
+                lengths[array],
+                len
+            ));
+        }
+    }
+    if let Some((array, _)) = arrays
+        .iter()
+        .find(|(array, _)| array.starts_with(INTERNAL_PREFIX))
+    {
+        return Err(anyhow!("Reserved array name found: {}", array));
+    }
+    if let Some((array, _)) = arrays.iter().find(|(array, _)| variables.contains(array)) {
+        return Err(anyhow!("`{}` is both an array and a variable", array));
+    }
+    let mut elements = vec![];
+    list_elements(ast.statements(), &mut elements);
+    for (array, index) in elements {
+        let Some(len) = lengths.get(&**array) else {
+            return Err(anyhow!("Array `{}` is not declared", &**array));
+        };
+        if let Expr::Num(num) = index {
+            if u8::from(num) >= *len {
+                return Err(anyhow!(
+                    "Index {} is past the end of `{}` of {} elements",
+                    u8::from(num),
+                    &**array,
+                    len
+                ));
+            }
+        }
+    }
+    Ok(arrays)
+}
+
 fn check_reserved_variables(variables: &HashSet<String>) -> Result<()> {
     for var in RESERVED_VARIABLES.iter() {
         if variables.contains(*var) {
             return Err(anyhow!("Reserved variable name found: {}", var));
         }
     }
+    if let Some(var) = variables
+        .iter()
+        .filter(|var| var.starts_with(INTERNAL_PREFIX))
+        .min()
+    {
+        return Err(anyhow!("Reserved variable name found: {}", var));
+    }
     Ok(())
 }
 
-pub fn code_gen(ast: &AST) -> Result<String> {
+fn bool_temps(cond: &Bool) -> usize {
+    cond.compares()
+        .iter()
+        .map(|c| match c {
+            Compare::Order(var, op, right) => expr_temps(&order_expr(var, *op, right)),
+            _ => 0,
+        })
+        .max()
+        .unwrap_or(0)
+}
+
+// Temporaries the expressions and ordered comparisons of the program use at
+// most.
+fn count_temps(statements: &[Statement]) -> usize {
+    statements
+        .iter()
+        .map(|stmt| match stmt {
+            Statement::Compute(var, expr) if in_place(var, expr).is_none() => expr_temps(expr),
+            Statement::Store(_, index, expr) => store_temps(index, expr),
+            Statement::WHILE(cond, func) => bool_temps(cond).max(count_temps(func.statements())),
+            Statement::IF(cond, if_func, else_func) => {
+                bool_temps(cond).max(count_temps(if_func.statements())).max(
+                    else_func
+                        .as_ref()
+                        .map_or(0, |f| count_temps(f.statements())),
+                )
+            }
+            _ => 0,
+        })
+        .max()
+        .unwrap_or(0)
+}
+
+// Shift the head `frames` frames to the right before the program starts so
+// that it can move left of its starting frame on a left-bounded tape.
+fn generate_tape_origin(frames: usize) -> Vec<Asm> {
+    vec![Asm::Rs(Value::new_const(CELL_SIZE)); frames]
+}
+
+// `source` is the text `ast` was parsed from, given to mark the code of every
+// statement with its location.
+pub fn code_gen(ast: &AST, source: Option<&str>, tape_origin: usize) -> Result<String> {
     let variables = list_variables(ast);
     check_reserved_variables(&variables)?;
+    let arrays = check_arrays(ast, &variables)?;
     let mut variables = variables.iter().map(|s| s.as_str()).collect::<Vec<&str>>();
+    // Keep the layout stable between runs.
+    variables.sort();
     variables.extend_from_slice(&RESERVED_VARIABLES);
-    let variable_define = variables
+    let temps = (0..count_temps(ast.statements()))
+        .map(temp)
+        .collect::<Vec<_>>();
+    variables.extend(temps.iter().map(String::as_str));
+    let mut cells = variables
         .iter()
         .enumerate()
-        .map(|(i, s)| format!("#define {} {}", s, i))
+        .map(|(i, s)| (s.to_string(), i))
+        .collect::<Vec<_>>();
+    // The elements of an array follow each other, then the walkers and the
+    // runway they move onto.
+    let mut size = variables.len();
+    for (array, len) in &arrays {
+        let len = usize::from(*len);
+        cells.extend((0..len).map(|k| (element_name(array, k as u8), size + k)));
+        cells.extend((0..WALKERS).map(|i| (walker(array, i).to_string(), size + len + i)));
+        size += 2 * len + WALKERS - 1;
+    }
+    let variable_define = cells
+        .iter()
+        .map(|(s, i)| format!("#define {} {}", s, i))
+        .chain(
+            arrays
+                .iter()
+                .map(|(array, len)| format!("#define {} {}", array_len(array), len)),
+        )
         .collect::<Vec<String>>()
         .join("\n");
-    let cell_size = format!("#define __cell_size {}", variables.len());
-    let asm = Vec::<Asm>::from(ast);
+    let carry_define = list_carried(ast)
+        .iter()
+        .map(|reg| {
+            let offset = variables.iter().position(|v| v == reg).unwrap() + size;
+            format!("#define {} {}", next_frame(reg), offset)
+        })
+        .collect::<Vec<String>>();
+    let variable_define = [vec![variable_define], carry_define].concat().join("\n");
+    let scratch = variables.iter().position(|v| *v == TEMP_VAR).unwrap();
+    let cell_size = format!(
+        "#define {} {}\n#define {} {}\n#define {} {}",
+        CELL_SIZE, size, SCRATCH, scratch, TAPE_ORIGIN, tape_origin
+    );
+    let asm = [generate_tape_origin(tape_origin), generate(ast, source)].concat();
     let asm = asm
         .iter()
         .map(|a| a.to_string())
@@ -268,6 +1145,7 @@ pub fn code_gen(ast: &AST) -> Result<String> {
 }
 
 #[cfg(test)]
+#[allow(clippy::module_inception)]
 mod generator {
     use super::*;
     use crate::{assembler::Variable, scanner::TokenStream};
@@ -347,20 +1225,235 @@ mod generator {
         assert_eq!(asm, expect);
     }
     #[test]
+    fn test_compute() {
+        let program = "x = a + b";
+        let asm = compile(program).unwrap();
+        let expect = vec![
+            Asm::Copy(
+                Variable::new("a"),
+                vec![Variable::new(TEMP_VAR), Variable::new("__expr0")],
+            ),
+            Asm::Copy(Variable::new(TEMP_VAR), vec![Variable::new("a")]),
+            Asm::Copy(
+                Variable::new("b"),
+                vec![Variable::new(TEMP_VAR), Variable::new("__expr1")],
+            ),
+            Asm::Copy(Variable::new(TEMP_VAR), vec![Variable::new("b")]),
+            Asm::Loop(Variable::new("__expr1")),
+            Asm::Sub(Variable::new("__expr1"), Value::new_num(1)),
+            Asm::Add(Variable::new("__expr0"), Value::new_num(1)),
+            Asm::End(Variable::new("__expr1")),
+            Asm::Copy(Variable::new("__expr0"), vec![Variable::new("x")]),
+        ];
+        assert_eq!(asm, expect);
+        let defines = |program: &str| {
+            let tokens = TokenStream::try_from(program).unwrap();
+            let ast = AST::try_from(&tokens).unwrap();
+            let asm = code_gen(&ast, None, 0).unwrap();
+            asm.matches("#define __expr").count()
+        };
+        assert_eq!(defines("x = a * ( b / c )"), 8);
+        assert_eq!(defines("x = a * ( b / 3 )"), 6);
+        assert_eq!(defines("x = x + 1 y = y - 2"), 0);
+    }
+    #[test]
+    fn test_compute_constant() {
+        let load = generate_load("a", "__expr0");
+        let store = vec![Asm::Copy(
+            Variable::new("__expr0"),
+            vec![Variable::new("x")],
+        )];
+        let expr0 = Variable::new("__expr0");
+        let cases = [
+            (
+                "x = a - 3",
+                vec![Asm::Sub(expr0.clone(), Value::new_num(3))],
+            ),
+            (
+                "x = 3 + a",
+                vec![Asm::Add(expr0.clone(), Value::new_num(3))],
+            ),
+            (
+                "x = 3 * a",
+                [
+                    vec![Asm::Copy(expr0.clone(), vec![Variable::new("__expr1")])],
+                    generate_drain("__expr1", vec![Asm::Add(expr0.clone(), Value::new_num(3))]),
+                ]
+                .concat(),
+            ),
+            (
+                "x = a * 0",
+                vec![Asm::Set(expr0.clone(), Value::new_num(0))],
+            ),
+            (
+                "x = a / 0",
+                vec![Asm::Set(expr0.clone(), Value::new_num(0))],
+            ),
+            ("x = a % 0", vec![]),
+            ("x = a / 1", vec![]),
+            (
+                "x = a % 1",
+                vec![Asm::Set(expr0.clone(), Value::new_num(0))],
+            ),
+        ];
+        for (program, op) in cases {
+            let expect = [load.clone(), op, store.clone()].concat();
+            assert_eq!(compile(program).unwrap(), expect, "{}", program);
+        }
+        assert_eq!(
+            compile("x = x + 5 x = 2 + x x = x - 1").unwrap(),
+            vec![
+                Asm::Add(Variable::new("x"), Value::new_num(5)),
+                Asm::Add(Variable::new("x"), Value::new_num(2)),
+                Asm::Sub(Variable::new("x"), Value::new_num(1)),
+            ]
+        );
+    }
+    #[test]
+    fn test_constant_index() {
+        let asm = compile("let t [ 4 ] t [ 3 ] = a x = t [ 1 ]").unwrap();
+        let expect = [
+            generate_load("a", "__expr0"),
+            vec![Asm::Copy(
+                Variable::new("__expr0"),
+                vec![Variable::new("t[3]")],
+            )],
+            generate_load("t[1]", "__expr0"),
+            vec![Asm::Copy(
+                Variable::new("__expr0"),
+                vec![Variable::new("x")],
+            )],
+        ]
+        .concat();
+        assert_eq!(asm, expect);
+    }
+    #[test]
+    fn test_array_layout() {
+        let program = "carry ( s ) let t [ 2 ] let u [ 3 ] t [ 1 ] = 1 u [ s ] = 2";
+        let tokens = TokenStream::try_from(program).unwrap();
+        let asm = code_gen(&AST::try_from(&tokens).unwrap(), None, 0).unwrap();
+        // s, the reserved variables and seven temporaries, then t with its
+        // walkers and their runway, then u.
+        let base = 1 + RESERVED_VARIABLES.len() + 7;
+        for (name, offset) in [
+            ("t[0]", base),
+            ("t[1]", base + 1),
+            ("__t_walk0", base + 2),
+            ("__t_walk3", base + 5),
+            ("u[0]", base + 7),
+            ("u[2]", base + 9),
+            ("__u_walk0", base + 10),
+            ("__u_len", 3),
+            ("__next_s", base + 16),
+            ("__cell_size", base + 16),
+        ] {
+            assert!(
+                asm.contains(&format!("#define {} {}\n", name, offset)),
+                "{}",
+                name
+            );
+        }
+        let error = |program: &str| {
+            let tokens = TokenStream::try_from(program).unwrap();
+            code_gen(&AST::try_from(&tokens).unwrap(), None, 0)
+                .unwrap_err()
+                .to_string()
+        };
+        assert_eq!(
+            error("let t [ 2 ] x = t [ 2 ]"),
+            "Index 2 is past the end of `t` of 2 elements"
+        );
+        assert_eq!(error("t [ 0 ] = 1"), "Array `t` is not declared");
+        assert_eq!(
+            error("let t [ 2 ] if t == 1 { let t [ 3 ] }"),
+            "Array `t` is declared with 2 and 3 elements"
+        );
+        assert_eq!(
+            error("let t [ 2 ] input ( t )"),
+            "`t` is both an array and a variable"
+        );
+        assert_eq!(
+            error("let t [ 3 ] __t_walk2 = 5 input ( i ) t [ i ] = 4"),
+            "Reserved variable name found: __t_walk2"
+        );
+        assert_eq!(
+            error("let t [ 3 ] __t_len = 1"),
+            "Reserved variable name found: __t_len"
+        );
+        assert_eq!(
+            error("let __t [ 3 ] __t [ 0 ] = 1"),
+            "Reserved array name found: __t"
+        );
+    }
+    #[test]
     fn test_move_right() {
         let program = "move_right";
         let asm = compile(program).unwrap();
-        let expect = vec![Asm::Rs(Value::new_const("__cell_size"))];
+        let expect = vec![Asm::Rs(Value::new_const(CELL_SIZE))];
         assert_eq!(asm, expect);
     }
     #[test]
     fn test_move_left() {
         let program = "move_left";
         let asm = compile(program).unwrap();
-        let expect = vec![Asm::Ls(Value::new_const("__cell_size"))];
+        let expect = vec![Asm::Ls(Value::new_const(CELL_SIZE))];
+        assert_eq!(asm, expect);
+    }
+    #[test]
+    fn test_carry_move_right() {
+        let program = "carry ( state ) state = 1 move_right";
+        let asm = compile(program).unwrap();
+        let expect = vec![
+            Asm::Set(Variable::new("state"), Value::new_num(1)),
+            Asm::Copy(Variable::new("state"), vec![Variable::new("__next_state")]),
+            Asm::Rs(Value::new_const(CELL_SIZE)),
+        ];
+        assert_eq!(asm, expect);
+    }
+    #[test]
+    fn test_carry_move_left() {
+        let program = "move_left carry ( state )";
+        let asm = compile(program).unwrap();
+        let expect = vec![
+            Asm::Ls(Value::new_const(CELL_SIZE)),
+            Asm::Copy(Variable::new("__next_state"), vec![Variable::new("state")]),
+        ];
         assert_eq!(asm, expect);
     }
     #[test]
+    fn test_carry_define() {
+        let program = "carry ( state )";
+        let tokens = TokenStream::try_from(program).unwrap();
+        let ast = AST::try_from(&*tokens.into_tokens()).unwrap();
+        let asm = code_gen(&ast, None, 0).unwrap();
+        let cell_size = 1 + RESERVED_VARIABLES.len();
+        assert!(asm.contains("#define state 0\n"));
+        assert!(asm.contains(&format!("#define __next_state {}\n", cell_size)));
+        assert!(asm.contains(&format!("#define __cell_size {}\n", cell_size)));
+    }
+    #[test]
+    fn test_internal_names() {
+        for program in [
+            "__scratch = 1",
+            "output ( __cell_size )",
+            "carry ( __next_x )",
+        ] {
+            let tokens = TokenStream::try_from(program).unwrap();
+            let ast = AST::try_from(&tokens).unwrap();
+            assert!(code_gen(&ast, None, 0).is_err(), "{}", program);
+        }
+    }
+    #[test]
+    fn test_tape_origin() {
+        let program = "carry ( state ) move_left";
+        let tokens = TokenStream::try_from(program).unwrap();
+        let ast = AST::try_from(&*tokens.into_tokens()).unwrap();
+        let asm = code_gen(&ast, None, 2).unwrap();
+        let expect = "rs __cell_size\nrs __cell_size\nls __cell_size\ncopy __next_state state";
+        assert!(asm.ends_with(expect));
+        assert!(asm.contains("#define __tape_origin 2\n"));
+    }
+    #[test]
     fn test_single_ne_condition_if() {
         let program = "if a != 10 { input ( x ) }";
         let asm = compile(program).unwrap();
@@ -430,7 +1523,6 @@ mod generator {
         let program = "if a == 10 { input ( x ) } else { input ( y ) }";
         let asm = compile(program).unwrap();
         let expect = vec![
-            Asm::Set(Variable::new(ELSE_FLAG), Value::new_num(1)),
             Asm::Set(Variable::new(IS_EQ), Value::new_num(1)),
             Asm::Copy(
                 Variable::new("a"),
@@ -439,18 +1531,53 @@ mod generator {
             Asm::Copy(Variable::new(TEMP_VAR), vec![Variable::new("a")]),
             Asm::Sub(Variable::new(IF_FLAG), Value::new_num(10)),
             Asm::Loop(Variable::new(IF_FLAG)),
+            Asm::Read(Variable::new("y")),
             Asm::Set(Variable::new(IS_EQ), Value::new_num(0)),
             Asm::Set(Variable::new(IF_FLAG), Value::new_num(0)),
             Asm::End(Variable::new(IF_FLAG)),
             Asm::Loop(Variable::new(IS_EQ)),
             Asm::Read(Variable::new("x")),
-            Asm::Set(Variable::new(ELSE_FLAG), Value::new_num(0)),
             Asm::Set(Variable::new(IS_EQ), Value::new_num(0)),
             Asm::End(Variable::new(IS_EQ)),
-            Asm::Loop(Variable::new(ELSE_FLAG)),
+        ];
+        assert_eq!(asm, expect);
+    }
+    #[test]
+    fn test_eq_chain() {
+        let program = "if a == 1 { input ( x ) } else { if a == 3 { input ( y ) } else { if a == 2 { input ( z ) } } }";
+        let asm = compile(program).unwrap();
+        let expect = vec![
+            Asm::Set(Variable::new(IS_EQ), Value::new_num(1)),
+            Asm::Copy(
+                Variable::new("a"),
+                vec![Variable::new(TEMP_VAR), Variable::new(IF_FLAG)],
+            ),
+            Asm::Copy(Variable::new(TEMP_VAR), vec![Variable::new("a")]),
+            Asm::Sub(Variable::new(IF_FLAG), Value::new_num(1)),
+            Asm::Loop(Variable::new(IF_FLAG)),
+            Asm::Sub(Variable::new(IF_FLAG), Value::new_num(2)),
+            Asm::Loop(Variable::new(IF_FLAG)),
+            Asm::Sub(Variable::new(IF_FLAG), Value::new_num(255)),
+            Asm::Loop(Variable::new(IF_FLAG)),
+            Asm::Set(Variable::new(IS_EQ), Value::new_num(0)),
+            Asm::Set(Variable::new(IF_FLAG), Value::new_num(0)),
+            Asm::End(Variable::new(IF_FLAG)),
+            Asm::Loop(Variable::new(IS_EQ)),
+            Asm::Read(Variable::new("z")),
+            Asm::Set(Variable::new(IF_FLAG), Value::new_num(0)),
+            Asm::Set(Variable::new(IS_EQ), Value::new_num(0)),
+            Asm::End(Variable::new(IS_EQ)),
+            Asm::End(Variable::new(IF_FLAG)),
+            Asm::Loop(Variable::new(IS_EQ)),
             Asm::Read(Variable::new("y")),
-            Asm::Set(Variable::new(ELSE_FLAG), Value::new_num(0)),
-            Asm::End(Variable::new(ELSE_FLAG)),
+            Asm::Set(Variable::new(IF_FLAG), Value::new_num(0)),
+            Asm::Set(Variable::new(IS_EQ), Value::new_num(0)),
+            Asm::End(Variable::new(IS_EQ)),
+            Asm::End(Variable::new(IF_FLAG)),
+            Asm::Loop(Variable::new(IS_EQ)),
+            Asm::Read(Variable::new("x")),
+            Asm::Set(Variable::new(IS_EQ), Value::new_num(0)),
+            Asm::End(Variable::new(IS_EQ)),
         ];
         assert_eq!(asm, expect);
     }
@@ -487,6 +1614,35 @@ mod generator {
         assert_eq!(asm, expect);
     }
     #[test]
+    fn test_order_condition_if() {
+        let program = "if a >= 10 { input ( x ) }";
+        let asm = compile(program).unwrap();
+        let expr = Expr::Binary(
+            Op::GE,
+            Box::new(Expr::Variable(
+                parser::Variable::new("a".to_string()).unwrap(),
+            )),
+            Box::new(Expr::Num(parser::Num::from(10))),
+        );
+        let expect = [
+            generate_expr(&expr, 0),
+            vec![
+                Asm::Copy(Variable::new("__expr0"), vec![Variable::new(IF_FLAG)]),
+                Asm::Loop(Variable::new(IF_FLAG)),
+                Asm::Read(Variable::new("x")),
+                Asm::Set(Variable::new(IF_FLAG), Value::new_num(0)),
+                Asm::End(Variable::new(IF_FLAG)),
+            ],
+        ]
+        .concat();
+        assert_eq!(asm, expect);
+        test_list_variables(&[("while a < b { }", HashSet::from(["a", "b"]))]);
+        let tokens = TokenStream::try_from("while a < b { }").unwrap();
+        let asm = code_gen(&AST::try_from(&tokens).unwrap(), None, 0).unwrap();
+        assert!(asm.contains("#define __expr4 "));
+        assert!(!asm.contains("#define __expr5 "));
+    }
+    #[test]
     fn test_multi_condition_if_ne() {
         let program = "if a != 10 && b == 11 { input ( x ) }";
         let asm = compile(program).unwrap();
@@ -0,0 +1,324 @@
+// Editor support on top of the scanner and parser, independent of any
+// protocol. Locations are 1-based lines and character columns as everywhere
+// else; the language server converts them to the positions of its clients.
+//
+// Variables have no declarations, so the definition of a variable is its
+// `carry` declaration if it has one, its first write otherwise. The definition
+// of an array is its `let` declaration.
+
+use crate::compiler::check;
+use crate::generator::{code_gen, list_arrays, list_carried, list_variables};
+use crate::layout::Layout;
+use crate::optimizer::optimize;
+use crate::parser::{self, element_name, Expr, Statement, AST};
+use crate::scanner::{Location, Token, TokenStream};
+use anyhow::Result;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Problem {
+    pub location: Location,
+    // Characters marked from `location` on.
+    pub length: usize,
+    pub severity: Severity,
+    pub message: String,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum Role {
+    Declaration,
+    Write,
+    Read,
+}
+
+// The word of `source` starting at `location`.
+fn word_length(source: &str, location: Location) -> usize {
+    source
+        .lines()
+        .nth(location.line - 1)
+        .map(|line| {
+            line.chars()
+                .skip(location.column - 1)
+                .take_while(|c| !c.is_whitespace())
+                .count()
+        })
+        .unwrap_or(0)
+}
+
+// Scanner errors are located at the invalid token.
+fn scan_problem(source: &str, message: String) -> Problem {
+    let invalid = source
+        .split_whitespace()
+        .find(|word| Token::try_from(*word).is_err());
+    let location = invalid
+        .and_then(|word| Location::find(source, word))
+        .unwrap_or(Location { line: 1, column: 1 });
+    Problem {
+        location,
+        length: invalid.map_or(0, |word| word.chars().count()),
+        severity: Severity::Error,
+        message,
+    }
+}
+
+// Syntax errors, or the warnings of `bf-compiler check` once the program parses.
+pub fn problems(source: &str) -> Vec<Problem> {
+    let tokens = match TokenStream::try_from(source) {
+        Ok(tokens) => tokens,
+        Err(err) => return vec![scan_problem(source, err.to_string())],
+    };
+    if let Err(errors) = parser::parse(tokens.cursor()) {
+        return errors
+            .into_iter()
+            .map(|err| Problem {
+                location: err
+                    .span
+                    .map_or(Location { line: 1, column: 1 }, |s| s.location),
+                length: err.span.map_or(0, |span| span.length),
+                severity: Severity::Error,
+                message: err.message,
+            })
+            .collect();
+    }
+    let diagnostics = match check(source) {
+        Ok((_, diagnostics)) => diagnostics,
+        Err(err) => return vec![scan_problem(source, err.to_string())],
+    };
+    diagnostics
+        .into_iter()
+        .map(|diagnostic| {
+            let location = diagnostic
+                .location
+                .unwrap_or(Location { line: 1, column: 1 });
+            Problem {
+                location,
+                length: word_length(source, location),
+                severity: Severity::Warning,
+                message: diagnostic.message,
+            }
+        })
+        .collect()
+}
+
+// The variables and arrays an expression reads.
+fn collect_expr<'a>(expr: &'a Expr, occurrences: &mut Vec<(&'a str, Role)>) {
+    let names = expr.variables().into_iter().chain(expr.arrays());
+    occurrences.extend(names.map(|v| (&**v, Role::Read)));
+}
+
+fn collect<'a>(statements: &'a [Statement], occurrences: &mut Vec<(&'a str, Role)>) {
+    for stmt in statements {
+        match stmt {
+            Statement::Carry(var) | Statement::Let(var, _) => {
+                occurrences.push((&**var, Role::Declaration))
+            }
+            Statement::Assign(var, _) | Statement::Input(var) => {
+                occurrences.push((&**var, Role::Write))
+            }
+            Statement::Compute(var, expr) => {
+                occurrences.push((&**var, Role::Write));
+                collect_expr(expr, occurrences);
+            }
+            Statement::Store(array, index, expr) => {
+                occurrences.push((&**array, Role::Write));
+                collect_expr(index, occurrences);
+                collect_expr(expr, occurrences);
+            }
+            Statement::Output(var) => occurrences.push((&**var, Role::Read)),
+            Statement::Move(..) => {}
+            Statement::IF(cond, if_func, else_func) => {
+                occurrences.extend(
+                    cond.compares()
+                        .iter()
+                        .flat_map(|c| c.variables())
+                        .map(|v| (&**v, Role::Read)),
+                );
+                collect(if_func.statements(), occurrences);
+                if let Some(else_func) = else_func {
+                    collect(else_func.statements(), occurrences);
+                }
+            }
+            Statement::WHILE(cond, func) => {
+                occurrences.extend(
+                    cond.compares()
+                        .iter()
+                        .flat_map(|c| c.variables())
+                        .map(|v| (&**v, Role::Read)),
+                );
+                collect(func.statements(), occurrences);
+            }
+        }
+    }
+}
+
+// Every occurrence of every variable in source order.
+fn occurrences(source: &str, ast: &AST) -> Vec<(Location, String, Role)> {
+    let mut found = vec![];
+    collect(ast.statements(), &mut found);
+    let mut occurrences = found
+        .into_iter()
+        .filter_map(|(name, role)| Some((Location::find(source, name)?, name.to_string(), role)))
+        .collect::<Vec<_>>();
+    occurrences.sort_by_key(|(location, _, _)| *location);
+    occurrences
+}
+
+fn parse(source: &str) -> Result<AST<'_>> {
+    let tokens = TokenStream::try_from(source)?;
+    AST::try_from(&tokens)
+}
+
+// The variable at `location`, which may also be just after its last character.
+fn variable_at(occurrences: &[(Location, String, Role)], location: Location) -> Option<&str> {
+    occurrences
+        .iter()
+        .find(|(start, name, _)| {
+            start.line == location.line
+                && start.column <= location.column
+                && location.column <= start.column + name.chars().count()
+        })
+        .map(|(_, name, _)| name.as_str())
+}
+
+// Every occurrence of the variable at `location`.
+pub fn references(source: &str, location: Location) -> Vec<Location> {
+    let Ok(ast) = parse(source) else {
+        return vec![];
+    };
+    let occurrences = occurrences(source, &ast);
+    let Some(name) = variable_at(&occurrences, location) else {
+        return vec![];
+    };
+    occurrences
+        .iter()
+        .filter(|(_, var, _)| var == name)
+        .map(|(location, _, _)| *location)
+        .collect()
+}
+
+// The `carry` or `let` declaration of the name at `location`, or its first
+// write.
+pub fn definition(source: &str, location: Location) -> Option<Location> {
+    let ast = parse(source).ok()?;
+    let occurrences = occurrences(source, &ast);
+    let name = variable_at(&occurrences, location)?;
+    let of_name = || occurrences.iter().filter(|(_, var, _)| var == name);
+    of_name()
+        .find(|(_, _, role)| *role == Role::Declaration)
+        .or_else(|| of_name().find(|(_, _, role)| *role == Role::Write))
+        .map(|(location, _, _)| *location)
+}
+
+// Where the variable at `location` is kept within a frame of the compiled
+// program.
+pub fn hover(source: &str, location: Location) -> Option<String> {
+    let ast = parse(source).ok()?;
+    let occurrences = occurrences(source, &ast);
+    let name = variable_at(&occurrences, location)?;
+    let ast = optimize(&ast);
+    if let Some((_, len)) = list_arrays(&ast)
+        .into_iter()
+        .find(|(array, _)| array == name)
+    {
+        let layout = Layout::from_asm(&code_gen(&ast, None, 0).ok()?).ok()?;
+        return Some(format!(
+            "`{}`: cells {} to {} of the {} cells of a frame",
+            name,
+            layout.offset(&element_name(name, 0))?,
+            layout.offset(&element_name(name, len - 1))?,
+            layout.cell_size
+        ));
+    }
+    if !list_variables(&ast).contains(name) {
+        return Some(format!("`{}` is removed by the optimizer", name));
+    }
+    let layout = Layout::from_asm(&code_gen(&ast, None, 0).ok()?).ok()?;
+    let mut text = format!(
+        "`{}`: cell {} of the {} cells of a frame",
+        name,
+        layout.offset(name)?,
+        layout.cell_size
+    );
+    if list_carried(&ast).iter().any(|reg| reg == name) {
+        text.push_str(", carried along when the head moves");
+    }
+    Some(text)
+}
+
+#[cfg(test)]
+#[allow(clippy::module_inception)]
+mod ide {
+    use super::*;
+
+    fn at(line: usize, column: usize) -> Location {
+        Location { line, column }
+    }
+    #[test]
+    fn test_problems() {
+        let found = problems("x = 1\noutput ( x ) ?");
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].location, at(2, 14));
+        assert_eq!(found[0].length, 1);
+        assert_eq!(found[0].severity, Severity::Error);
+
+        let found = problems("x = 1 = 2\nwhile x != 0 {\n  if x == y { }\n}\ninput ( 1 )");
+        let found = found
+            .iter()
+            .map(|p| (p.location, p.length, p.message.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            found,
+            [
+                (at(1, 7), 1, "Expected a statement, found `=`"),
+                (at(3, 11), 1, "Expected a number, found `y`"),
+                (at(5, 9), 1, "Expected a variable, found `1`")
+            ]
+        );
+
+        let found = problems("output ( y )");
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].location, at(1, 10));
+        assert_eq!(found[0].severity, Severity::Warning);
+    }
+    #[test]
+    fn test_references() {
+        let source = "x = 1\nwhile x != 0 {\n  output ( x )\n  y = 2\n}";
+        let expect = vec![at(1, 1), at(2, 7), at(3, 12)];
+        assert_eq!(references(source, at(3, 13)), expect);
+        assert_eq!(references(source, at(2, 1)), vec![]);
+    }
+    #[test]
+    fn test_definition() {
+        let source = "output ( x )\ninput ( x )\ncarry ( x )";
+        assert_eq!(definition(source, at(1, 10)), Some(at(3, 9)));
+        let source = "output ( x )\ninput ( x )\nx = 1";
+        assert_eq!(definition(source, at(3, 1)), Some(at(2, 9)));
+    }
+    #[test]
+    fn test_hover() {
+        let source = "carry ( b )\na = 1\noutput ( a )\noutput ( b )";
+        let hover_b = hover(source, at(4, 10)).unwrap();
+        assert_eq!(
+            hover_b,
+            "`b`: cell 1 of the 7 cells of a frame, carried along when the head moves"
+        );
+        assert_eq!(hover(source, at(1, 1)), None);
+        let source = "let t [ 3 ]\nt [ 1 ] = 2\nx = t [ i ]";
+        assert_eq!(
+            hover(source, at(3, 5)).unwrap(),
+            "`t`: cells 13 to 15 of the 22 cells of a frame"
+        );
+        assert_eq!(definition(source, at(2, 1)), Some(at(1, 5)));
+        assert_eq!(references(source, at(1, 5)), [at(1, 5), at(2, 1), at(3, 5)]);
+        let source = "x = 1\nif x == 2 { let t [ 3 ] }\nt [ 1 ] = 2\ny = t [ i ]";
+        assert_eq!(
+            hover(source, at(3, 1)).unwrap(),
+            "`t`: cells 14 to 16 of the 23 cells of a frame"
+        );
+    }
+}
@@ -0,0 +1,185 @@
+// Brainfuck interpreter.
+//
+// Cells are wrapping bytes and the tape grows to the right on demand. Moving
+// left of cell 0 is an error. Reading past the end of the input stores 0.
+
+use anyhow::{anyhow, Result};
+use std::io::{Read, Write};
+
+#[derive(Debug)]
+pub struct Interpreter {
+    program: Vec<u8>,
+    jumps: Vec<usize>,
+    pc: usize,
+    tape: Vec<u8>,
+    head: usize,
+    steps: u64,
+    counts: Option<Vec<u64>>,
+}
+
+fn match_brackets(program: &[u8]) -> Result<Vec<usize>> {
+    let mut jumps = vec![0; program.len()];
+    let mut open = vec![];
+    for (i, c) in program.iter().enumerate() {
+        match c {
+            b'[' => open.push(i),
+            b']' => {
+                let start = open.pop().ok_or_else(|| anyhow!("Unmatched ] at {}", i))?;
+                jumps[start] = i;
+                jumps[i] = start;
+            }
+            _ => {}
+        }
+    }
+    match open.pop() {
+        Some(start) => Err(anyhow!("Unmatched [ at {}", start)),
+        None => Ok(jumps),
+    }
+}
+
+impl Interpreter {
+    pub fn new(program: &str) -> Result<Self> {
+        let program = program.as_bytes().to_vec();
+        let jumps = match_brackets(&program)?;
+        Ok(Self {
+            program,
+            jumps,
+            pc: 0,
+            tape: vec![0],
+            head: 0,
+            steps: 0,
+            counts: None,
+        })
+    }
+    // Count how many times each character of the program is executed from
+    // now on.
+    pub fn profile(&mut self) {
+        self.counts = Some(vec![0; self.program.len()]);
+    }
+    pub fn counts(&self) -> Option<&[u64]> {
+        self.counts.as_deref()
+    }
+    // Position in the program of the next instruction.
+    pub fn pc(&self) -> usize {
+        self.pc
+    }
+    pub fn head(&self) -> usize {
+        self.head
+    }
+    // Cells up to the rightmost one the head has reached.
+    pub fn tape(&self) -> &[u8] {
+        &self.tape
+    }
+    // Cells the head has not reached yet hold zero.
+    pub fn cell(&self, index: usize) -> u8 {
+        self.tape.get(index).copied().unwrap_or(0)
+    }
+    pub fn is_halted(&self) -> bool {
+        self.pc >= self.program.len()
+    }
+    // Number of instructions executed so far, comments excluded.
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+    // Execute the next instruction. Characters other than the eight
+    // instructions are skipped without counting as a step.
+    pub fn step(&mut self, input: &mut impl Read, output: &mut impl Write) -> Result<()> {
+        let pc = self.pc;
+        let Some(&instruction) = self.program.get(pc) else {
+            return Ok(());
+        };
+        match instruction {
+            b'>' => {
+                self.head += 1;
+                if self.head == self.tape.len() {
+                    self.tape.push(0);
+                }
+            }
+            b'<' => {
+                if self.head == 0 {
+                    return Err(anyhow!("Head moved left of cell 0 at {}", self.pc));
+                }
+                self.head -= 1;
+            }
+            b'+' => self.tape[self.head] = self.tape[self.head].wrapping_add(1),
+            b'-' => self.tape[self.head] = self.tape[self.head].wrapping_sub(1),
+            b'.' => output.write_all(&[self.tape[self.head]])?,
+            b',' => {
+                let mut buf = [0];
+                self.tape[self.head] = match input.read(&mut buf)? {
+                    0 => 0,
+                    _ => buf[0],
+                };
+            }
+            b'[' if self.tape[self.head] == 0 => self.pc = self.jumps[self.pc],
+            b']' if self.tape[self.head] != 0 => self.pc = self.jumps[self.pc],
+            b'[' | b']' => {}
+            _ => {
+                self.pc += 1;
+                return Ok(());
+            }
+        }
+        if let Some(counts) = &mut self.counts {
+            counts[pc] += 1;
+        }
+        self.pc += 1;
+        self.steps += 1;
+        Ok(())
+    }
+    pub fn run(&mut self, input: &mut impl Read, output: &mut impl Write) -> Result<()> {
+        while !self.is_halted() {
+            self.step(input, output)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::module_inception)]
+mod interpreter {
+    use super::*;
+    fn run(program: &str, input: &str) -> (Vec<u8>, Interpreter) {
+        let mut interpreter = Interpreter::new(program).unwrap();
+        let mut output = vec![];
+        interpreter.run(&mut input.as_bytes(), &mut output).unwrap();
+        (output, interpreter)
+    }
+    #[test]
+    fn test_arithmetic() {
+        let (_, interpreter) = run("+++>--<-", "");
+        assert_eq!(interpreter.tape, &[2, 254]);
+        assert_eq!(interpreter.head, 0);
+        assert_eq!(interpreter.steps(), 8);
+    }
+    #[test]
+    fn test_loop() {
+        let (_, interpreter) = run("+++[->++<]", "");
+        assert_eq!(interpreter.tape, &[0, 6]);
+    }
+    #[test]
+    fn test_io() {
+        let (output, _) = run(",+.,.", "a");
+        assert_eq!(output, b"b\0");
+    }
+    #[test]
+    fn test_comments() {
+        let (output, interpreter) = run("hello +.", "");
+        assert_eq!(output, &[1]);
+        assert_eq!(interpreter.steps(), 2);
+    }
+    #[test]
+    fn test_profile() {
+        let mut interpreter = Interpreter::new("++[->+<]").unwrap();
+        interpreter.profile();
+        interpreter.run(&mut [].as_slice(), &mut vec![]).unwrap();
+        let expect = [1, 1, 1, 2, 2, 2, 2, 2];
+        assert_eq!(interpreter.counts(), Some(expect.as_slice()));
+    }
+    #[test]
+    fn test_errors() {
+        assert!(Interpreter::new("[[]").is_err());
+        assert!(Interpreter::new("[]]").is_err());
+        let mut interpreter = Interpreter::new("<").unwrap();
+        assert!(interpreter.run(&mut [].as_slice(), &mut vec![]).is_err());
+    }
+}
@@ -0,0 +1,126 @@
+// Layout of a frame, read back from the defines of the generated assembly.
+
+use crate::generator::RESERVED_VARIABLES;
+use anyhow::{anyhow, Result};
+
+#[derive(Debug, PartialEq)]
+pub struct Layout {
+    pub cell_size: usize,
+    // The frame the program starts in, frames are numbered relative to it.
+    pub origin: usize,
+    // The program's own variables with their offsets within a frame, in
+    // layout order. Reserved variables and internal defines are left out.
+    pub variables: Vec<(String, usize)>,
+}
+
+impl Layout {
+    pub fn from_asm(asm: &str) -> Result<Self> {
+        let mut cell_size = None;
+        let mut origin = 0;
+        let mut variables = vec![];
+        for command in asm.split('\n') {
+            let parts = command.split(' ').collect::<Vec<_>>();
+            let ["#define", name, val] = parts[..] else {
+                continue;
+            };
+            let val = val.parse::<usize>()?;
+            if name == "__cell_size" {
+                cell_size = Some(val);
+            } else if name == "__tape_origin" {
+                origin = val;
+            } else if !name.starts_with("__") && !RESERVED_VARIABLES.contains(&name) {
+                variables.push((name.to_string(), val));
+            }
+        }
+        let cell_size = cell_size.ok_or_else(|| anyhow!("No __cell_size defined"))?;
+        Ok(Self {
+            cell_size,
+            origin,
+            variables,
+        })
+    }
+    // Frame of the cell at `index`, relative to the starting frame.
+    pub fn frame(&self, index: usize) -> i64 {
+        (index / self.cell_size) as i64 - self.origin as i64
+    }
+    // One row per frame of `tape` and one column per variable. The frame of the
+    // head is marked with `>`, and with the offset of the head within the frame
+    // when it is not on the first cell.
+    pub fn render(&self, tape: &[u8], head: usize) -> String {
+        let widths = self
+            .variables
+            .iter()
+            .map(|(name, _)| name.len().max(3))
+            .collect::<Vec<_>>();
+        let mut table = format!("  {:>5}", "frame");
+        for ((name, _), width) in self.variables.iter().zip(&widths) {
+            table.push_str(&format!("  {:>width$}", name));
+        }
+        table.push('\n');
+        let frames = tape.len().max(head + 1).div_ceil(self.cell_size);
+        for frame in 0..frames {
+            let base = frame * self.cell_size;
+            let marker = if head / self.cell_size == frame {
+                ">"
+            } else {
+                " "
+            };
+            table.push_str(&format!("{} {:>5}", marker, self.frame(base)));
+            for ((_, offset), width) in self.variables.iter().zip(&widths) {
+                let value = tape.get(base + offset).copied().unwrap_or(0);
+                table.push_str(&format!("  {:>width$}", value));
+            }
+            if head / self.cell_size == frame && head != base {
+                table.push_str(&format!("  (head at cell {})", head - base));
+            }
+            table.push('\n');
+        }
+        table
+    }
+    pub fn offset(&self, name: &str) -> Option<usize> {
+        self.variables
+            .iter()
+            .find(|(var, _)| var == name)
+            .map(|(_, offset)| *offset)
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::module_inception)]
+mod layout {
+    use super::*;
+    #[test]
+    fn test_from_asm() {
+        let asm = "#define sym 0\n#define state 1\n#define tmp 2\n#define __next_state 4\n\
+                   #define __cell_size 3\n#define __scratch 2\nrs __cell_size";
+        let layout = Layout::from_asm(asm).unwrap();
+        let variables = vec![("sym".to_string(), 0), ("state".to_string(), 1)];
+        assert_eq!(layout.cell_size, 3);
+        assert_eq!(layout.origin, 0);
+        assert_eq!(layout.variables, variables);
+        assert_eq!(layout.offset("state"), Some(1));
+        assert_eq!(layout.offset("tmp"), None);
+        assert!(Layout::from_asm("#define x 0").is_err());
+    }
+    #[test]
+    fn test_render() {
+        let asm = "#define sym 0\n#define state 1\n#define tmp 2\n\
+                   #define __cell_size 3\n#define __tape_origin 1";
+        let layout = Layout::from_asm(asm).unwrap();
+        let tape = [1, 2, 0, 3, 200, 0, 5];
+        let expect = [
+            "  frame  sym  state",
+            "     -1    1      2",
+            ">     0    3    200",
+            "      1    5      0",
+        ];
+        assert_eq!(layout.render(&tape, 3), expect.join("\n") + "\n");
+        let expect = [
+            "  frame  sym  state",
+            "     -1    1      2",
+            "      0    3    200",
+            ">     1    5      0  (head at cell 1)",
+        ];
+        assert_eq!(layout.render(&tape, 7), expect.join("\n") + "\n");
+    }
+}
@@ -0,0 +1,45 @@
+//! A compiler from brainfuck-c (bfc) to Brainfuck.
+//!
+//! bfc source is compiled to an intermediate assembly, which is assembled
+//! into Brainfuck:
+//!
+//! ```
+//! use bf_compiler::{assemble, compile, CompileOptions};
+//!
+//! let compilation = compile("x = 72 output ( x )", &CompileOptions::default())?;
+//! let program = assemble(&compilation.asm)?;
+//! assert!(program.contains('.'));
+//! # Ok::<(), anyhow::Error>(())
+//! ```
+//!
+//! The steps can also be taken one at a time, from the tokens of
+//! [`TokenStream`] to the syntax tree of [`AST`] and the instructions of
+//! [`Asm`]. The items re-exported here are the stable API of the crate; the
+//! modules are public for the tools built with it and may change between
+//! versions.
+
+pub mod analyzer;
+pub mod assembler;
+pub mod builder;
+pub mod checker;
+pub mod compiler;
+pub mod debugger;
+pub mod evaluator;
+pub mod formatter;
+pub mod generator;
+pub mod ide;
+pub mod interpreter;
+pub mod layout;
+pub mod optimizer;
+pub mod parser;
+pub mod profiler;
+pub mod random;
+pub mod scanner;
+pub mod sourcemap;
+
+pub use assembler::assemble;
+pub use builder::{Block, Condition};
+pub use compiler::{compile, compile_ast, Compilation, CompileOptions};
+pub use generator::Asm;
+pub use parser::AST;
+pub use scanner::{Location, TokenStream};
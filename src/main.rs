@@ -1,29 +1,236 @@
-mod assembler;
-mod compiler;
-mod generator;
-mod parser;
-mod scanner;
-use crate::assembler::assemble;
-use crate::compiler::compile;
-use anyhow::Result;
-use clap::Parser;
+use anyhow::{anyhow, Result};
+use bf_compiler::assembler::assemble_with_map;
+use bf_compiler::compiler::{check, differential, format_program};
+use bf_compiler::debugger::Debugger;
+use bf_compiler::interpreter::Interpreter;
+use bf_compiler::layout::Layout;
+use bf_compiler::profiler::Profile;
+use bf_compiler::sourcemap::SourceMap;
+use bf_compiler::{assemble, compile, CompileOptions};
+use clap::{Parser, Subcommand};
 use std::fs::File;
-use std::io::{stdout, Write};
+use std::io::{stdin, stdout, BufRead, Write};
 
 #[derive(Parser, Debug)]
-#[command(version, about, long_about = None)]
+#[command(version, about, long_about = None, args_conflicts_with_subcommands = true)]
 struct Args {
-    source: String,
+    #[command(subcommand)]
+    command: Option<Command>,
+    source: Option<String>,
     #[arg(short, long)]
     output: Option<String>,
+    /// Frames to shift the head right before running, computed from the program if omitted
+    #[arg(long)]
+    tape_origin: Option<usize>,
+    /// Write a JSON map from Brainfuck character ranges to the assembly and source
+    #[arg(long)]
+    source_map: Option<String>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Report static diagnostics without compiling
+    Check { source: String },
+    /// Compile and run a program with the built-in interpreter
+    Run {
+        source: String,
+        /// Frames to shift the head right before running, computed from the program if omitted
+        #[arg(long)]
+        tape_origin: Option<usize>,
+        /// Print the number of executed instructions to stderr
+        #[arg(long)]
+        steps: bool,
+        /// Print the statements and instructions that executed the most steps to stderr
+        #[arg(long)]
+        profile: bool,
+        /// Print the tape as a table of frames and variables to stderr when the program halts
+        #[arg(long)]
+        tape: bool,
+    },
+    /// Compare the compiled program against a direct evaluation of the source
+    Diff {
+        source: String,
+        /// File to feed to the program as input, the program reads EOF if omitted
+        #[arg(long)]
+        input: Option<String>,
+        /// Statements or instructions either run may execute before giving up
+        #[arg(long, default_value_t = 100_000_000)]
+        max_steps: u64,
+    },
+    /// Step through a program statement by statement
+    Debug {
+        source: String,
+        /// Frames to shift the head right before running, computed from the program if omitted
+        #[arg(long)]
+        tape_origin: Option<usize>,
+        /// File to feed to the program as input, the program reads EOF if omitted
+        #[arg(long)]
+        input: Option<String>,
+    },
+    /// Rewrite programs in place in canonical formatting
+    Fmt {
+        #[arg(required = true)]
+        sources: Vec<String>,
+        /// List the files that are not formatted instead of rewriting them
+        #[arg(long)]
+        check: bool,
+    },
+}
+
+// Compile to assembly, printing the warnings to stderr.
+fn compile_with_warnings(source: &str, tape_origin: Option<usize>) -> Result<String> {
+    let options = CompileOptions {
+        tape_origin,
+        ..CompileOptions::default()
+    };
+    let compilation = compile(source, &options)?;
+    for warning in compilation.warnings {
+        eprintln!("warning: {}", warning);
+    }
+    Ok(compilation.asm)
+}
+
+fn run_check(source: &str) -> Result<()> {
+    let source = std::fs::read_to_string(source)?;
+    let (reach, diagnostics) = check(&source)?;
+    println!("reachable frames: {}", reach);
+    for diagnostic in diagnostics {
+        println!("{}", diagnostic);
+    }
+    Ok(())
+}
+
+fn run_program(
+    source: &str,
+    tape_origin: Option<usize>,
+    steps: bool,
+    profile: bool,
+    tape: bool,
+) -> Result<()> {
+    let source = std::fs::read_to_string(source)?;
+    let asm = compile_with_warnings(&source, tape_origin)?;
+    let (program, lines) = assemble_with_map(&asm)?;
+    let mut interpreter = Interpreter::new(&program)?;
+    if profile {
+        interpreter.profile();
+    }
+    if let Err(err) = interpreter.run(&mut stdin(), &mut stdout()) {
+        let source_map = SourceMap::new(&asm, &lines)?;
+        return match source_map.find(interpreter.pc()).and_then(|m| m.location) {
+            Some(location) => Err(err.context(format!("in the statement at {}", location))),
+            None => Err(err),
+        };
+    }
+    if steps {
+        eprintln!("steps: {}", interpreter.steps());
+    }
+    if let Some(counts) = interpreter.counts() {
+        eprint!("{}", Profile::new(&source, &asm, &lines, counts)?);
+    }
+    if tape {
+        let layout = Layout::from_asm(&asm)?;
+        eprint!("{}", layout.render(interpreter.tape(), interpreter.head()));
+    }
+    Ok(())
+}
+
+fn run_differential(source: &str, input: Option<String>, max_steps: u64) -> Result<()> {
+    let source = std::fs::read_to_string(source)?;
+    let input = input.map_or(Ok(vec![]), std::fs::read)?;
+    let differences = differential(&source, &input, max_steps)?;
+    for difference in &differences {
+        println!("{}", difference);
+    }
+    if differences.is_empty() {
+        println!("no differences");
+        Ok(())
+    } else {
+        Err(anyhow!("{} difference(s) found", differences.len()))
+    }
+}
+
+fn run_debugger(source: &str, tape_origin: Option<usize>, input: Option<String>) -> Result<()> {
+    let source = std::fs::read_to_string(source)?;
+    let input = input.map_or(Ok(vec![]), std::fs::read)?;
+    let asm = compile_with_warnings(&source, tape_origin)?;
+    let (program, lines) = assemble_with_map(&asm)?;
+    let mut output = stdout();
+    let mut debugger = Debugger::new(&source, &asm, &program, &lines, input, &mut output)?;
+    debugger.execute("where", &mut output)?;
+    let mut commands = stdin().lock().lines();
+    loop {
+        print!("(bfc) ");
+        output.flush()?;
+        let Some(command) = commands.next().transpose()? else {
+            break;
+        };
+        match debugger.execute(&command, &mut output) {
+            Ok(true) => {}
+            Ok(false) => break,
+            Err(err) => println!("error: {}", err),
+        }
+    }
+    Ok(())
+}
+
+fn run_formatter(sources: &[String], check: bool) -> Result<()> {
+    let mut unformatted = 0;
+    for file_name in sources {
+        let source = std::fs::read_to_string(file_name)?;
+        let formatted =
+            format_program(&source).map_err(|err| err.context(format!("in {}", file_name)))?;
+        if formatted == source {
+            continue;
+        }
+        if check {
+            println!("{}", file_name);
+            unformatted += 1;
+        } else {
+            std::fs::write(file_name, formatted)?;
+        }
+    }
+    if unformatted > 0 {
+        return Err(anyhow!("{} file(s) are not formatted", unformatted));
+    }
+    Ok(())
 }
 
 fn main() -> Result<()> {
     let args = Args::parse();
-    let source = std::fs::read_to_string(args.source).unwrap();
-    let asm = compile(&source)?;
+    match args.command {
+        Some(Command::Check { source }) => return run_check(&source),
+        Some(Command::Run {
+            source,
+            tape_origin,
+            steps,
+            profile,
+            tape,
+        }) => return run_program(&source, tape_origin, steps, profile, tape),
+        Some(Command::Diff {
+            source,
+            input,
+            max_steps,
+        }) => return run_differential(&source, input, max_steps),
+        Some(Command::Debug {
+            source,
+            tape_origin,
+            input,
+        }) => return run_debugger(&source, tape_origin, input),
+        Some(Command::Fmt { sources, check }) => return run_formatter(&sources, check),
+        None => {}
+    }
+    let source = args.source.ok_or_else(|| anyhow!("No source file given"))?;
+    let source = std::fs::read_to_string(source)?;
+    let asm = compile_with_warnings(&source, args.tape_origin)?;
     println!("{}", asm);
-    let program = assemble(&asm)?;
+    let program = match args.source_map {
+        Some(file_name) => {
+            let (program, lines) = assemble_with_map(&asm)?;
+            std::fs::write(file_name, SourceMap::new(&asm, &lines)?.to_json())?;
+            program
+        }
+        None => assemble(&asm)?,
+    };
     let mut output: Box<dyn Write> = if let Some(file_name) = args.output {
         let output_file = File::create(file_name)?;
         Box::new(output_file)
@@ -0,0 +1,326 @@
+// Constant folding on the AST.
+//
+// Values are tracked for the variables of the current frame only. Nothing is
+// known about a frame the head has just moved to, except for the carried
+// registers which move along with the head.
+
+use crate::generator::list_carried;
+use crate::parser::{Bool, Compare, Expr, Function, Num, Statement, AST};
+use std::collections::HashMap;
+
+type Known = HashMap<String, u8>;
+
+fn evaluate(compare: &Compare, known: &Known) -> Option<bool> {
+    compare.evaluate(&|var| known.get(var).copied())
+}
+
+// Drop the comparisons that are known to hold. Returns `None` when the
+// condition is known to be false and an empty condition when it is known to
+// be true.
+fn fold_condition<'a>(cond: &Bool<'a>, known: &Known) -> Option<Bool<'a>> {
+    let mut compares = vec![];
+    for compare in cond.compares() {
+        match evaluate(compare, known) {
+            Some(true) => {}
+            Some(false) => return None,
+            None => compares.push(compare.clone()),
+        }
+    }
+    Some(Bool::new(compares))
+}
+
+// Values implied by the condition being true.
+fn facts_if_true(cond: &Bool) -> Known {
+    cond.compares()
+        .iter()
+        .filter_map(|c| match c {
+            Compare::EQ(var, val) => Some((var.to_string(), u8::from(val))),
+            Compare::NE(..) | Compare::Order(..) => None,
+        })
+        .collect()
+}
+
+// Values implied by the condition being false.
+fn facts_if_false(cond: &Bool) -> Known {
+    match cond.compares() {
+        [Compare::NE(var, val)] => Known::from([(var.to_string(), u8::from(val))]),
+        _ => Known::new(),
+    }
+}
+
+fn intersect(a: &Known, b: &Known) -> Known {
+    a.iter()
+        .filter(|(var, val)| b.get(*var) == Some(*val))
+        .map(|(var, val)| (var.clone(), *val))
+        .collect()
+}
+
+// Declarations apply to the whole program, so the ones in a dropped branch
+// are kept at the place of the branch.
+fn declarations<'a>(statements: &[Statement<'a>]) -> Vec<Statement<'a>> {
+    let mut found = vec![];
+    for stmt in statements {
+        match stmt {
+            Statement::IF(_, if_func, else_func) => {
+                found.extend(declarations(if_func.statements()));
+                if let Some(else_func) = else_func {
+                    found.extend(declarations(else_func.statements()));
+                }
+            }
+            Statement::WHILE(_, func) => found.extend(declarations(func.statements())),
+            Statement::Carry(_) | Statement::Let(..) => found.push(stmt.clone()),
+            _ => {}
+        }
+    }
+    found
+}
+
+fn fold_if<'a>(
+    cond: &Bool<'a>,
+    if_func: &Function<'a>,
+    else_func: &Option<Function<'a>>,
+    known: &mut Known,
+    carried: &[String],
+) -> Vec<Statement<'a>> {
+    let Some(folded) = fold_condition(cond, known) else {
+        let mut statements = declarations(if_func.statements());
+        if let Some(else_func) = else_func {
+            statements.extend(fold(else_func.statements(), known, carried));
+        }
+        return statements;
+    };
+    let mut if_known = known.clone();
+    if_known.extend(facts_if_true(cond));
+    let mut if_statements = fold(if_func.statements(), &mut if_known, carried);
+    if folded.compares().is_empty() {
+        *known = if_known;
+        if let Some(else_func) = else_func {
+            if_statements.extend(declarations(else_func.statements()));
+        }
+        return if_statements;
+    }
+    let mut else_known = known.clone();
+    else_known.extend(facts_if_false(&folded));
+    let else_statements = else_func
+        .as_ref()
+        .map(|f| fold(f.statements(), &mut else_known, carried))
+        .filter(|statements| !statements.is_empty());
+    *known = intersect(&if_known, &else_known);
+    if if_statements.is_empty() && else_statements.is_none() {
+        return vec![];
+    }
+    vec![Statement::IF(
+        folded,
+        Function::new(if_statements),
+        else_statements.map(Function::new),
+    )]
+}
+
+// The values known at the head of the loop are the ones that survive any
+// number of iterations, found by folding the body until nothing else is lost.
+fn fold_while<'a>(
+    cond: &Bool<'a>,
+    func: &Function<'a>,
+    known: &mut Known,
+    carried: &[String],
+) -> Vec<Statement<'a>> {
+    if fold_condition(cond, known).is_none() {
+        return declarations(func.statements());
+    }
+    loop {
+        let mut body_known = known.clone();
+        body_known.extend(facts_if_true(cond));
+        fold(func.statements(), &mut body_known, carried);
+        let next = intersect(known, &body_known);
+        if next == *known {
+            break;
+        }
+        *known = next;
+    }
+    let cond = match fold_condition(cond, known) {
+        Some(folded) if !folded.compares().is_empty() => folded,
+        _ => cond.clone(),
+    };
+    let mut body_known = known.clone();
+    body_known.extend(facts_if_true(&cond));
+    let body = fold(func.statements(), &mut body_known, carried);
+    known.extend(facts_if_false(&cond));
+    vec![Statement::WHILE(cond, Function::new(body))]
+}
+
+fn fold<'a>(
+    statements: &[Statement<'a>],
+    known: &mut Known,
+    carried: &[String],
+) -> Vec<Statement<'a>> {
+    let mut folded = vec![];
+    for stmt in statements {
+        match stmt {
+            Statement::Assign(var, val) => {
+                if known.get(&**var) != Some(&u8::from(val)) {
+                    known.insert(var.to_string(), u8::from(val));
+                    folded.push(stmt.clone());
+                }
+            }
+            Statement::Compute(var, expr) => {
+                match expr.evaluate(&|name| known.get(name).copied()) {
+                    Some(val) => folded.extend(fold(
+                        &[Statement::Assign(var.clone(), Num::from(val))],
+                        known,
+                        carried,
+                    )),
+                    None => {
+                        known.remove(&**var);
+                        folded.push(stmt.clone());
+                    }
+                }
+            }
+            // Only the value is folded: a known index past the end of the
+            // array would not compile as a constant.
+            Statement::Store(array, index, expr) => {
+                let expr = match expr.evaluate(&|name| known.get(name).copied()) {
+                    Some(val) => Expr::Num(Num::from(val)),
+                    None => expr.clone(),
+                };
+                folded.push(Statement::Store(array.clone(), index.clone(), expr));
+            }
+            Statement::Input(var) => {
+                known.remove(&**var);
+                folded.push(stmt.clone());
+            }
+            Statement::Move(..) => {
+                known.retain(|var, _| carried.contains(var));
+                folded.push(stmt.clone());
+            }
+            Statement::IF(cond, if_func, else_func) => {
+                folded.extend(fold_if(cond, if_func, else_func, known, carried))
+            }
+            Statement::WHILE(cond, func) => folded.extend(fold_while(cond, func, known, carried)),
+            Statement::Output(_) | Statement::Carry(_) | Statement::Let(..) => {
+                folded.push(stmt.clone())
+            }
+        }
+    }
+    folded
+}
+
+// Remove assignments of values a variable already holds and branches whose
+// condition is known, starting with nothing known about the tape.
+pub fn optimize<'a>(ast: &AST<'a>) -> AST<'a> {
+    let carried = list_carried(ast);
+    let statements = fold(ast.statements(), &mut Known::new(), &carried);
+    AST::new(Function::new(statements))
+}
+
+#[cfg(test)]
+#[allow(clippy::module_inception)]
+mod optimizer {
+    use super::*;
+    use crate::scanner::TokenStream;
+    fn test_optimize(program: &str, expect: &str) {
+        let tokens = TokenStream::try_from(program).unwrap().into_tokens();
+        let ast = AST::try_from(&*tokens).unwrap();
+        let tokens = TokenStream::try_from(expect).unwrap().into_tokens();
+        let expect = AST::try_from(&*tokens).unwrap();
+        assert_eq!(optimize(&ast), expect);
+    }
+    #[test]
+    fn test_redundant_assign() {
+        test_optimize("x = 1 x = 1 output ( x )", "x = 1 output ( x )");
+        test_optimize("x = 1 input ( x ) x = 1", "x = 1 input ( x ) x = 1");
+    }
+    #[test]
+    fn test_fold_expression() {
+        test_optimize(
+            "a = 6 b = a * 7 + 2 output ( b )",
+            "a = 6 b = 44 output ( b )",
+        );
+        test_optimize(
+            "a = 44 input ( c ) b = a + c a = b - c",
+            "a = 44 input ( c ) b = a + c a = b - c",
+        );
+        test_optimize("a = 2 b = a / 2 + 1 b = 2", "a = 2 b = 2");
+    }
+    #[test]
+    fn test_known_if() {
+        test_optimize(
+            "sym = 3 if sym == 3 { output ( sym ) } else { input ( sym ) }",
+            "sym = 3 output ( sym )",
+        );
+        test_optimize(
+            "sym = 3 if sym != 3 { output ( sym ) } else { input ( sym ) }",
+            "sym = 3 input ( sym )",
+        );
+        test_optimize("sym = 3 if sym == 4 { output ( sym ) }", "sym = 3");
+    }
+    #[test]
+    fn test_known_order() {
+        test_optimize(
+            "a = 3 b = 5 if a < b && b >= 5 { output ( a ) } else { input ( a ) }",
+            "a = 3 b = 5 output ( a )",
+        );
+        test_optimize(
+            "a = 3 input ( b ) if a > 3 { output ( a ) } if a <= b { output ( b ) }",
+            "a = 3 input ( b ) if a <= b { output ( b ) }",
+        );
+    }
+    #[test]
+    fn test_partially_known_if() {
+        test_optimize(
+            "a = 1 if a == 1 && b == 2 { output ( a ) }",
+            "a = 1 if b == 2 { output ( a ) }",
+        );
+    }
+    #[test]
+    fn test_facts_from_condition() {
+        test_optimize(
+            "input ( s ) if s == 0 { s = 0 move_left } else { if s != 1 { output ( s ) } else { s = 1 } }",
+            "input ( s ) if s == 0 { move_left } else { if s != 1 { output ( s ) } }",
+        );
+    }
+    #[test]
+    fn test_merge_branches() {
+        test_optimize(
+            "input ( c ) if c == 1 { x = 2 } else { x = 2 } x = 2 output ( x )",
+            "input ( c ) if c == 1 { x = 2 } else { x = 2 } output ( x )",
+        );
+    }
+    #[test]
+    fn test_move_forgets() {
+        test_optimize(
+            "carry ( s ) s = 1 x = 1 move_right s = 1 x = 1",
+            "carry ( s ) s = 1 x = 1 move_right x = 1",
+        );
+    }
+    #[test]
+    fn test_dead_declarations() {
+        test_optimize(
+            "s = 1 if s == 2 { carry ( s ) output ( s ) } move_right output ( s )",
+            "s = 1 carry ( s ) move_right output ( s )",
+        );
+        test_optimize(
+            "s = 1 if s == 1 { output ( s ) } else { if s == 3 { carry ( s ) } }",
+            "s = 1 output ( s ) carry ( s )",
+        );
+        test_optimize(
+            "s = 0 while s != 0 { carry ( s ) input ( s ) }",
+            "s = 0 carry ( s )",
+        );
+        test_optimize(
+            "x = 1 if x == 2 { let t [ 3 ] } t [ 0 ] = 65",
+            "x = 1 let t [ 3 ] t [ 0 ] = 65",
+        );
+        test_optimize(
+            "x = 0 while x != 0 { let t [ 3 ] input ( x ) } t [ 0 ] = 65",
+            "x = 0 let t [ 3 ] t [ 0 ] = 65",
+        );
+    }
+    #[test]
+    fn test_while() {
+        test_optimize("s = 0 while s != 0 { input ( s ) }", "s = 0");
+        test_optimize(
+            "s = 1 x = 2 while s != 0 { x = 2 input ( s ) } s = 0 output ( x )",
+            "s = 1 x = 2 while s != 0 { input ( s ) } output ( x )",
+        );
+    }
+}
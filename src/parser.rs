@@ -1,47 +1,78 @@
 // Reserved words:
 // - IF: if
 // - ELSE: else
+// - LET: let
 // - NC: next_cell
 // - PC: prev_cell
 //
 // Semantic:
 // - AST: Function EOF
 // - Function: [Statement]*
-// - Statement: If | While | Assign | Move | Input | Output
+// - Statement: If | While | Assign | Store | Let | Move | Input | Output | Carry
 // - If: if Bool { Function } [else { Function }]!
 // - While: while Bool { Function }
-// - Assign: Variable = NUMBER
+// - Assign: Variable = Expr
+// - Store: Variable [ Expr ] = Expr
+// - Let: let Variable [ NUMBER ]
 // - Bool: Compare [&& Compare]*
-// - Compare: Equal | NotEqual
+// - Compare: Equal | NotEqual | Order
 // - Equal: Variable == NUMBER
 // - NotEqual: Variable != NUMBER
+// - Order: Variable (< | <= | > | >=) (NUMBER | Variable)
 // - Move: ID("move_right") | ID("move_left")
 // - Input: ID("input") ( Variable )
 // - Output: ID("output") ( Variable )
+// - Carry: ID("carry") ( Variable )
 // - Variable: ID
+// - Expr: Sum [(< | <= | > | >=) Sum]!
+// - Sum: Product [(+ | -) Product]*
+// - Product: Atom [(* | / | %) Atom]*
+// - Atom: NUMBER | Variable | Variable [ Expr ] | ( Expr )
 
-use crate::scanner::{Token};
+use crate::scanner::{Cursor, Span, Token, TokenStream};
 use anyhow::{anyhow, Result};
+use std::borrow::Cow;
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct AST<'a>(Function<'a>);
 
 impl<'a> AST<'a> {
-    pub fn statements(&'a self) -> &'a [Statement<'a>] {
+    pub fn new(function: Function<'a>) -> Self {
+        Self(function)
+    }
+    pub fn statements(&self) -> &[Statement<'a>] {
         self.0.statements()
     }
+    // An AST that owns all of its names and no longer borrows the source.
+    pub fn into_owned(self) -> AST<'static> {
+        AST(self.0.into_owned())
+    }
+}
+
+// The canonical formatting of the program, without the source's blank lines.
+impl std::fmt::Display for AST<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", crate::formatter::format(self, None))
+    }
+}
+
+fn join_errors(errors: Vec<ParseError>) -> anyhow::Error {
+    let messages = errors.iter().map(ToString::to_string).collect::<Vec<_>>();
+    anyhow!(messages.join("\n"))
+}
+
+// Errors are located at their tokens.
+impl<'a> TryFrom<&TokenStream<'a>> for AST<'a> {
+    type Error = anyhow::Error;
+    fn try_from(tokens: &TokenStream<'a>) -> Result<Self> {
+        parse(tokens.cursor()).map_err(join_errors)
+    }
 }
 
 impl<'a> TryFrom<&[Token<'a>]> for AST<'a> {
     type Error = anyhow::Error;
-    fn try_from(tokens: &[Token<'a>]) -> std::prelude::v1::Result<Self, Self::Error> {
-        let function = Function::try_from(tokens)?;
-        let last_token = &tokens[function.len()];
-        if *last_token != Token::EOF {
-            Err(anyhow!("Expected EOF, found {:?}", last_token))
-        } else {
-            Ok(AST(function))
-        }
+    fn try_from(tokens: &[Token<'a>]) -> Result<Self> {
+        parse(Cursor::from(tokens)).map_err(join_errors)
     }
 }
 
@@ -49,27 +80,14 @@ impl<'a> TryFrom<&[Token<'a>]> for AST<'a> {
 pub struct Function<'a>(Vec<Statement<'a>>);
 
 impl<'a> Function<'a> {
-    fn len(&self) -> usize {
-        self.0.iter().map(Statement::len).sum()
+    pub fn new(statements: Vec<Statement<'a>>) -> Self {
+        Self(statements)
     }
-    pub fn statements(&'a self) -> &'a [Statement<'a>] {
+    pub fn statements(&self) -> &[Statement<'a>] {
         &self.0
     }
-}
-
-impl<'a> TryFrom<&[Token<'a>]> for Function<'a> {
-    type Error = anyhow::Error;
-    fn try_from(mut value: &[Token<'a>]) -> Result<Self> {
-        let mut statements = vec![];
-        while !value.is_empty() {
-            let Ok(statement) = Statement::try_from(value) else {
-                break;
-            };
-            let consumed = statement.len();
-            statements.push(statement);
-            value = &value[consumed..];
-        }
-        Ok(Self(statements))
+    pub fn into_owned(self) -> Function<'static> {
+        Function(self.0.into_iter().map(Statement::into_owned).collect())
     }
 }
 
@@ -78,201 +96,380 @@ pub enum Statement<'a> {
     IF(Bool<'a>, Function<'a>, Option<Function<'a>>),
     WHILE(Bool<'a>, Function<'a>),
     Assign(Variable<'a>, Num),
-    Move(Direction),
+    // An assignment of an expression other than a literal.
+    Compute(Variable<'a>, Expr<'a>),
+    // An assignment to the element of an array at an index.
+    Store(Variable<'a>, Expr<'a>, Expr<'a>),
+    // An array of a number of elements. Like carry, declarations apply to the
+    // whole program regardless of where they appear.
+    Let(Variable<'a>, Num),
+    // The keyword is kept to locate the statement in the source.
+    Move(Direction, Cow<'a, str>),
     Input(Variable<'a>),
     Output(Variable<'a>),
+    Carry(Variable<'a>),
 }
 
-impl Statement<'_> {
-    fn len(&self) -> usize {
+impl<'a> Statement<'a> {
+    // A fragment of the source the statement was parsed from, to locate it
+    // with `Location::find`. Statements built or rewritten by passes own their
+    // names and cannot be located.
+    pub fn anchor(&self) -> Option<&'a str> {
         match self {
-            Self::IF(bool, if_func, else_func) => {
-                3 + bool.len() + if_func.len() + else_func.as_ref().map_or(0, |f| 3 + f.len())
+            Self::IF(cond, _, _) | Self::WHILE(cond, _) => {
+                cond.compares().first()?.variable().borrowed()
             }
-            Self::WHILE(bool, func) => 3 + bool.len() + func.len(),
-            Self::Assign(_, _) => 3,
-            Self::Move(_) => 1,
-            Self::Input(_) => 4,
-            Self::Output(_) => 4,
+            Self::Assign(var, _)
+            | Self::Compute(var, _)
+            | Self::Store(var, _, _)
+            | Self::Let(var, _)
+            | Self::Input(var)
+            | Self::Output(var)
+            | Self::Carry(var) => var.borrowed(),
+            Self::Move(_, Cow::Borrowed(keyword)) => Some(keyword),
+            Self::Move(_, Cow::Owned(_)) => None,
         }
     }
-}
-
-fn try_parse_input<'a>(tokens: &[Token<'a>]) -> Result<Statement<'a>> {
-    if tokens.len() < 4 {
-        return Err(anyhow!(
-            "Expected at least 4 tokens, found {:?}",
-            tokens.len()
-        ));
-    }
-    match &tokens[..4] {
-        [Token::ID("input"), Token::LP, variable, Token::RP] => {
-            let variable = Variable::try_from(variable)?;
-            Ok(Statement::Input(variable))
+    pub fn into_owned(self) -> Statement<'static> {
+        match self {
+            Self::IF(cond, if_func, else_func) => Statement::IF(
+                cond.into_owned(),
+                if_func.into_owned(),
+                else_func.map(Function::into_owned),
+            ),
+            Self::WHILE(cond, func) => Statement::WHILE(cond.into_owned(), func.into_owned()),
+            Self::Assign(var, num) => Statement::Assign(var.into_owned(), num),
+            Self::Compute(var, expr) => Statement::Compute(var.into_owned(), expr.into_owned()),
+            Self::Store(var, index, expr) => {
+                Statement::Store(var.into_owned(), index.into_owned(), expr.into_owned())
+            }
+            Self::Let(var, len) => Statement::Let(var.into_owned(), len),
+            Self::Move(direction, keyword) => {
+                Statement::Move(direction, Cow::Owned(keyword.into_owned()))
+            }
+            Self::Input(var) => Statement::Input(var.into_owned()),
+            Self::Output(var) => Statement::Output(var.into_owned()),
+            Self::Carry(var) => Statement::Carry(var.into_owned()),
         }
-        _ => Err(anyhow!("Expected input ( Variable ), found {:?}", tokens)),
     }
 }
 
-fn try_parse_output<'a>(tokens: &[Token<'a>]) -> Result<Statement<'a>> {
-    if tokens.len() < 4 {
-        return Err(anyhow!(
-            "Expected at least 4 tokens, found {:?}",
-            tokens.len()
-        ));
-    }
-    match &tokens[..4] {
-        [Token::ID("output"), Token::LP, variable, Token::RP] => {
-            let variable = Variable::try_from(variable)?;
-            Ok(Statement::Output(variable))
+// A syntax error. The parser resumes at the next statement after one, so every
+// error of a program is reported at once.
+#[derive(Debug, PartialEq, Clone)]
+pub struct ParseError {
+    // The token the error was found at, if the tokens have spans.
+    pub span: Option<Span>,
+    pub message: String,
+}
+
+impl std::fmt::Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self.span {
+            Some(span) => write!(f, "{}: {}", span.location, self.message),
+            None => write!(f, "{}", self.message),
         }
-        _ => Err(anyhow!("Expected input ( Variable ), found {:?}", tokens)),
     }
 }
 
-fn try_parse_move<'a>(tokens: &[Token<'a>]) -> Result<Statement<'a>> {
-    if tokens.is_empty() {
-        return Err(anyhow!(
-            "Expected at least 1 token, found {:?}",
-            tokens.len()
-        ));
-    }
-    match &tokens[0] {
-        Token::ID("move_right") => Ok(Statement::Move(Direction::Right)),
-        Token::ID("move_left") => Ok(Statement::Move(Direction::Left)),
-        _ => Err(anyhow!(
-            "Expected move_right or move_left, found {:?}",
-            tokens
-        )),
+impl std::error::Error for ParseError {}
+
+// The program, or every syntax error in it.
+pub fn parse<'a>(tokens: Cursor<'_, 'a>) -> std::result::Result<AST<'a>, Vec<ParseError>> {
+    let mut parser = Parser::new(tokens);
+    let ast = parser.program();
+    if parser.errors.is_empty() {
+        Ok(ast)
+    } else {
+        Err(parser.errors)
     }
 }
 
-fn try_parse_assign<'a>(tokens: &[Token<'a>]) -> Result<Statement<'a>> {
-    if tokens.len() < 3 {
-        return Err(anyhow!(
-            "Expected at least 3 tokens, found {:?}",
-            tokens.len()
-        ));
+// Recursive descent, one method per rule of the grammar. A method starts at
+// the first token of its rule and returns right after the last one.
+struct Parser<'t, 'a> {
+    cursor: Cursor<'t, 'a>,
+    errors: Vec<ParseError>,
+}
+
+impl<'t, 'a> Parser<'t, 'a> {
+    fn new(cursor: Cursor<'t, 'a>) -> Self {
+        Self {
+            cursor,
+            errors: vec![],
+        }
     }
-    match &tokens[..3] {
-        [id, Token::ASSIGN, num] => {
-            let variable = Variable::try_from(id)?;
-            let num = Num::try_from(num)?;
-            Ok(Statement::Assign(variable, num))
+    fn peek(&self) -> Token<'a> {
+        self.cursor.peek()
+    }
+    // An error at the next token.
+    fn error(&self, message: String) -> ParseError {
+        ParseError {
+            span: self.cursor.span(),
+            message,
         }
-        _ => Err(anyhow!("Expected Variable = NUMBER, found {:?}", tokens)),
     }
-}
-
-fn try_parse_while<'a>(tokens: &[Token<'a>]) -> Result<Statement<'a>> {
-    if tokens.len() < 6 {
-        return Err(anyhow!(
-            "Expected at least 6 tokens, found {:?}",
-            tokens.len()
-        ));
+    fn expected(&self, what: &str) -> ParseError {
+        let found = match self.peek() {
+            Token::EOF => "end of file".to_string(),
+            token => format!("`{}`", token),
+        };
+        self.error(format!("Expected {}, found {}", what, found))
     }
-    match &tokens {
-        [Token::ID("while"), rest @ ..] => {
-            let bools = Bool::try_from(rest)?;
-            let rest = &rest[bools.len()..];
-            match rest {
-                [Token::LB, statements @ ..] => {
-                    let statements = Function::try_from(statements)?;
-                    let consumed = statements.len() + 1; // +1 for LB
-                    if rest[consumed] != Token::RB {
-                        Err(anyhow!("Expected }}, found {:?}", rest[statements.len()]))
-                    } else {
-                        Ok(Statement::WHILE(bools, statements))
-                    }
+    fn expect(&mut self, token: Token, what: &str) -> std::result::Result<(), ParseError> {
+        if self.peek() != token {
+            return Err(self.expected(what));
+        }
+        self.cursor.advance();
+        Ok(())
+    }
+    fn program(&mut self) -> AST<'a> {
+        let mut statements = self.statements();
+        // Only a `}` stops the statements before the end of the program.
+        while self.peek() != Token::EOF {
+            self.errors
+                .push(self.error("Found `}` without a matching `{`".to_string()));
+            self.cursor.advance();
+            statements.extend(self.statements());
+        }
+        AST(Function(statements))
+    }
+    // The statements up to the `}` of a block or the end of the program.
+    fn statements(&mut self) -> Vec<Statement<'a>> {
+        let mut statements = vec![];
+        while !matches!(self.peek(), Token::RB | Token::EOF) {
+            let start = self.cursor.position();
+            match self.statement() {
+                Ok(statement) => statements.push(statement),
+                Err(err) => {
+                    self.errors.push(err);
+                    self.recover(start);
                 }
-                _ => Err(anyhow!(
-                    "Expected while Bool {{ statements }}, found {:?}",
-                    tokens
-                )),
             }
         }
-        _ => Err(anyhow!(
-            "Expected while Bool {{ statements }}, found {:?}",
-            tokens
-        )),
-    }
-}
-
-fn try_parse_if<'a>(tokens: &[Token<'a>]) -> Result<Statement<'a>> {
-    if tokens.len() < 6 {
-        return Err(anyhow!(
-            "Expected at least 6 tokens, found {:?}",
-            tokens.len()
-        ));
-    }
-    match &tokens {
-        [Token::ID("if"), rest @ ..] => {
-            let bools = Bool::try_from(rest)?;
-            let rest = &rest[bools.len()..];
-            match rest {
-                [Token::LB, statements @ ..] => {
-                    let if_func = Function::try_from(statements)?;
-                    let last_token = &statements[if_func.len()];
-                    if *last_token != Token::RB {
-                        Err(anyhow!("Expected }}, found {:?}", last_token))
-                    } else {
-                        Ok(Statement::IF(bools, if_func, None))
-                    }
+        statements
+    }
+    fn at_statement(&self) -> bool {
+        match self.peek() {
+            Token::ID(
+                "if" | "while" | "let" | "input" | "output" | "carry" | "move_left" | "move_right",
+            ) => true,
+            Token::ID(_) => matches!(self.cursor.peek_at(1), Token::ASSIGN | Token::LSB),
+            Token::RB | Token::EOF => true,
+            _ => false,
+        }
+    }
+    // Skips the rest of a statement that failed to parse, blocks included, up
+    // to the start of the next statement.
+    fn recover(&mut self, start: usize) {
+        if self.cursor.position() == start && self.peek() != Token::LB {
+            self.cursor.advance();
+        }
+        let mut depth = 0;
+        loop {
+            match self.peek() {
+                Token::EOF => return,
+                Token::LB => depth += 1,
+                Token::RB if depth > 0 => depth -= 1,
+                _ if depth == 0 && self.at_statement() => return,
+                _ => {}
+            }
+            self.cursor.advance();
+        }
+    }
+    fn statement(&mut self) -> std::result::Result<Statement<'a>, ParseError> {
+        match self.peek() {
+            Token::ID("if") => self.if_statement(),
+            Token::ID("while") => self.while_statement(),
+            Token::ID("else") => Err(self.error("Found `else` without an `if`".to_string())),
+            Token::ID("input") => Ok(Statement::Input(self.argument()?)),
+            Token::ID("output") => Ok(Statement::Output(self.argument()?)),
+            Token::ID("carry") => Ok(Statement::Carry(self.argument()?)),
+            Token::ID("let") => self.let_statement(),
+            Token::ID(keyword @ "move_left") => {
+                self.cursor.advance();
+                Ok(Statement::Move(Direction::Left, Cow::Borrowed(keyword)))
+            }
+            Token::ID(keyword @ "move_right") => {
+                self.cursor.advance();
+                Ok(Statement::Move(Direction::Right, Cow::Borrowed(keyword)))
+            }
+            Token::ID(_) => {
+                let variable = self.variable()?;
+                if self.peek() == Token::LSB {
+                    let index = self.index()?;
+                    self.expect(Token::ASSIGN, "`=`")?;
+                    return Ok(Statement::Store(variable, index, self.expression()?));
                 }
-                _ => Err(anyhow!(
-                    "Expected while Bool {{ statements }}, found {:?}",
-                    tokens
-                )),
+                self.expect(Token::ASSIGN, "`=` or `[`")?;
+                Ok(match self.expression()? {
+                    Expr::Num(num) => Statement::Assign(variable, num),
+                    expr => Statement::Compute(variable, expr),
+                })
             }
+            _ => Err(self.expected("a statement")),
         }
-        _ => Err(anyhow!(
-            "Expected while Bool {{ statements }}, found {:?}",
-            tokens
-        )),
     }
-}
-
-fn try_parse_else<'a>(tokens: &[Token<'a>]) -> Option<Function<'a>> {
-    match tokens {
-        [Token::ID("else"), Token::LB, statements @ ..] => {
-            let else_func = Function::try_from(statements).ok()?;
-            let last_token = &statements[else_func.len()];
-            if *last_token != Token::RB {
-                None
-            } else {
-                Some(else_func)
+    fn if_statement(&mut self) -> std::result::Result<Statement<'a>, ParseError> {
+        self.cursor.advance();
+        let cond = self.condition()?;
+        let if_func = self.block("`&&` or `{`")?;
+        let else_func = if self.peek() == Token::ID("else") {
+            self.cursor.advance();
+            Some(self.block("`{`")?)
+        } else {
+            None
+        };
+        Ok(Statement::IF(cond, if_func, else_func))
+    }
+    fn while_statement(&mut self) -> std::result::Result<Statement<'a>, ParseError> {
+        self.cursor.advance();
+        let cond = self.condition()?;
+        let func = self.block("`&&` or `{`")?;
+        Ok(Statement::WHILE(cond, func))
+    }
+    fn let_statement(&mut self) -> std::result::Result<Statement<'a>, ParseError> {
+        self.cursor.advance();
+        let variable = self.variable()?;
+        self.expect(Token::LSB, "`[`")?;
+        if matches!(self.peek(), Token::NUM(len) if len.parse() == Ok(0)) {
+            return Err(self.error("An array has at least one element".to_string()));
+        }
+        let len = self.number()?;
+        self.expect(Token::RSB, "`]`")?;
+        Ok(Statement::Let(variable, len))
+    }
+    // `[ Expr ]` after the name of an array.
+    fn index(&mut self) -> std::result::Result<Expr<'a>, ParseError> {
+        self.cursor.advance();
+        let index = self.expression()?;
+        self.expect(Token::RSB, "an operator or `]`")?;
+        Ok(index)
+    }
+    // `( Variable )` after the keyword of input, output and carry.
+    fn argument(&mut self) -> std::result::Result<Variable<'a>, ParseError> {
+        self.cursor.advance();
+        self.expect(Token::LP, "`(`")?;
+        let variable = self.variable()?;
+        self.expect(Token::RP, "`)`")?;
+        Ok(variable)
+    }
+    // `expected` describes what may come before the `{`.
+    fn block(&mut self, expected: &str) -> std::result::Result<Function<'a>, ParseError> {
+        self.expect(Token::LB, expected)?;
+        let statements = self.statements();
+        self.expect(Token::RB, "a statement or `}`")?;
+        Ok(Function(statements))
+    }
+    fn condition(&mut self) -> std::result::Result<Bool<'a>, ParseError> {
+        let mut compares = vec![self.compare()?];
+        while self.peek() == Token::AND {
+            self.cursor.advance();
+            compares.push(self.compare()?);
+        }
+        Ok(Bool { compares })
+    }
+    fn compare(&mut self) -> std::result::Result<Compare<'a>, ParseError> {
+        let variable = self.variable()?;
+        let op = self.peek();
+        if let Some(op @ (Op::LT | Op::LE | Op::GT | Op::GE)) = Op::from_token(op) {
+            self.cursor.advance();
+            let right = match self.peek() {
+                Token::NUM(_) => Expr::Num(self.number()?),
+                Token::ID(_) => Expr::Variable(self.variable()?),
+                _ => return Err(self.expected("a number or a variable")),
+            };
+            return Ok(Compare::Order(variable, op, right));
+        }
+        if !matches!(op, Token::EQ | Token::NE) {
+            return Err(self.expected("a comparison operator"));
+        }
+        self.cursor.advance();
+        let num = self.number()?;
+        Ok(match op {
+            Token::EQ => Compare::EQ(variable, num),
+            _ => Compare::NE(variable, num),
+        })
+    }
+    fn expression(&mut self) -> std::result::Result<Expr<'a>, ParseError> {
+        let left = self.sum()?;
+        match Op::from_token(self.peek()) {
+            Some(op @ (Op::LT | Op::LE | Op::GT | Op::GE)) => {
+                self.cursor.advance();
+                let right = self.sum()?;
+                Ok(Expr::Binary(op, Box::new(left), Box::new(right)))
             }
+            _ => Ok(left),
         }
-        _ => None,
     }
-}
-
-fn try_parse_if_else<'a>(tokens: &[Token<'a>]) -> Result<Statement<'a>> {
-    let if_statement = try_parse_if(tokens)?;
-    let else_func = try_parse_else(&tokens[if_statement.len()..]);
-    let Statement::IF(bools, if_func, _) = if_statement else {
-        panic!("Expected IF statement, found {:?}", if_statement)
-    };
-    Ok(Statement::IF(bools, if_func, else_func))
+    fn sum(&mut self) -> std::result::Result<Expr<'a>, ParseError> {
+        let mut left = self.product()?;
+        while let Some(op @ (Op::ADD | Op::SUB)) = Op::from_token(self.peek()) {
+            self.cursor.advance();
+            left = Expr::Binary(op, Box::new(left), Box::new(self.product()?));
+        }
+        Ok(left)
+    }
+    fn product(&mut self) -> std::result::Result<Expr<'a>, ParseError> {
+        let mut left = self.atom()?;
+        while let Some(op @ (Op::MUL | Op::DIV | Op::MOD)) = Op::from_token(self.peek()) {
+            self.cursor.advance();
+            left = Expr::Binary(op, Box::new(left), Box::new(self.atom()?));
+        }
+        Ok(left)
+    }
+    fn atom(&mut self) -> std::result::Result<Expr<'a>, ParseError> {
+        match self.peek() {
+            Token::NUM(_) => Ok(Expr::Num(self.number()?)),
+            Token::ID(_) => {
+                let variable = self.variable()?;
+                if self.peek() != Token::LSB {
+                    return Ok(Expr::Variable(variable));
+                }
+                Ok(Expr::Element(variable, Box::new(self.index()?)))
+            }
+            Token::LP => {
+                self.cursor.advance();
+                let expr = self.expression()?;
+                self.expect(Token::RP, "an operator or `)`")?;
+                Ok(expr)
+            }
+            _ => Err(self.expected("a number, a variable or `(`")),
+        }
+    }
+    fn variable(&mut self) -> std::result::Result<Variable<'a>, ParseError> {
+        match self.peek() {
+            Token::ID(id) if RESERVED_WORDS.contains(&id) => {
+                let err = self.error(format!(
+                    "Expected a variable, found the reserved word `{}`",
+                    id
+                ));
+                // Meant as a variable, not as the start of the next statement.
+                self.cursor.advance();
+                Err(err)
+            }
+            Token::ID(id) => {
+                self.cursor.advance();
+                Ok(Variable(Cow::Borrowed(id)))
+            }
+            _ => Err(self.expected("a variable")),
+        }
+    }
+    fn number(&mut self) -> std::result::Result<Num, ParseError> {
+        let token = self.peek();
+        if !matches!(token, Token::NUM(_)) {
+            return Err(self.expected("a number"));
+        }
+        let num = Num::try_from(&token).map_err(|err| self.error(err.to_string()))?;
+        self.cursor.advance();
+        Ok(num)
+    }
 }
 
 impl<'a> TryFrom<&[Token<'a>]> for Statement<'a> {
     type Error = anyhow::Error;
-    fn try_from(value: &[Token<'a>]) -> Result<Self> {
-        let try_matches = [
-            try_parse_input,
-            try_parse_output,
-            try_parse_move,
-            try_parse_assign,
-            try_parse_while,
-            try_parse_if_else,
-        ];
-        for try_match in try_matches {
-            if let Ok(statement) = try_match(value) {
-                return Ok(statement);
-            }
-        }
-        Err(anyhow!("No match found for {:?}", value))
+    fn try_from(tokens: &[Token<'a>]) -> Result<Self> {
+        Ok(Parser::new(Cursor::from(tokens)).statement()?)
     }
 }
 
@@ -281,46 +478,22 @@ pub struct Bool<'a> {
     compares: Vec<Compare<'a>>,
 }
 
-impl Bool<'_> {
-    fn len(&self) -> usize {
-        if self.compares.is_empty() {
-            0
-        } else {
-            3 + 4 * (self.compares.len() - 1)
-        }
+impl<'a> Bool<'a> {
+    pub fn new(compares: Vec<Compare<'a>>) -> Self {
+        Self { compares }
     }
-    pub fn compares(&'_ self) -> &'_ [Compare<'_>] {
+    pub fn compares(&self) -> &[Compare<'a>] {
         &self.compares
     }
+    pub fn into_owned(self) -> Bool<'static> {
+        Bool::new(self.compares.into_iter().map(Compare::into_owned).collect())
+    }
 }
 
 impl<'a> TryFrom<&[Token<'a>]> for Bool<'a> {
     type Error = anyhow::Error;
-    fn try_from(value: &[Token<'a>]) -> Result<Self> {
-        if value.len() < 3 {
-            return Err(anyhow!(
-                "Expected at least 3 tokens, found {:?}",
-                value.len()
-            ));
-        }
-        let (first_compare, rest_compares) = value.split_at(3);
-        let Ok(compare) = Compare::try_from(first_compare) else {
-            return Err(anyhow!("Expected a compare, found {:?}", first_compare));
-        };
-        let mut compares = vec![compare];
-        let mut rest_compares = rest_compares.chunks_exact(4);
-        loop {
-            match rest_compares.next() {
-                Some([Token::AND, compare @ ..]) => {
-                    let Ok(compare) = Compare::try_from(compare) else {
-                        break;
-                    };
-                    compares.push(compare);
-                }
-                _ => break,
-            }
-        }
-        Ok(Self { compares })
+    fn try_from(tokens: &[Token<'a>]) -> Result<Self> {
+        Ok(Parser::new(Cursor::from(tokens)).condition()?)
     }
 }
 
@@ -328,20 +501,174 @@ impl<'a> TryFrom<&[Token<'a>]> for Bool<'a> {
 pub enum Compare<'a> {
     EQ(Variable<'a>, Num),
     NE(Variable<'a>, Num),
+    // An ordered comparison of the variable against a number or another
+    // variable, the operator is one of `<`, `<=`, `>` and `>=`.
+    Order(Variable<'a>, Op, Expr<'a>),
+}
+
+impl<'a> Compare<'a> {
+    pub fn variable(&self) -> &Variable<'a> {
+        match self {
+            Self::EQ(var, _) => var,
+            Self::NE(var, _) => var,
+            Self::Order(var, _, _) => var,
+        }
+    }
+    // The variables read by the comparison, from left to right.
+    pub fn variables(&self) -> Vec<&Variable<'a>> {
+        match self {
+            Self::Order(var, _, right) => [vec![var], right.variables()].concat(),
+            _ => vec![self.variable()],
+        }
+    }
+    // Whether the comparison holds, if `value` knows every variable it reads.
+    pub fn evaluate(&self, value: &impl Fn(&str) -> Option<u8>) -> Option<bool> {
+        match self {
+            Self::EQ(var, num) => Some(value(var)? == u8::from(num)),
+            Self::NE(var, num) => Some(value(var)? != u8::from(num)),
+            Self::Order(var, op, right) => Some(op.apply(value(var)?, right.evaluate(value)?) != 0),
+        }
+    }
+    pub fn into_owned(self) -> Compare<'static> {
+        match self {
+            Self::EQ(var, num) => Compare::EQ(var.into_owned(), num),
+            Self::NE(var, num) => Compare::NE(var.into_owned(), num),
+            Self::Order(var, op, right) => Compare::Order(var.into_owned(), op, right.into_owned()),
+        }
+    }
 }
 
 impl<'a> TryFrom<&[Token<'a>]> for Compare<'a> {
     type Error = anyhow::Error;
     fn try_from(tokens: &[Token<'a>]) -> Result<Self> {
-        if tokens.len() < 3 {
-            return Err(anyhow!("Expected at least 3 tokens, found {:?}", tokens));
+        Ok(Parser::new(Cursor::from(tokens)).compare()?)
+    }
+}
+
+// Arithmetic wraps around like the cells do, and comparisons are 1 if they
+// hold and 0 otherwise.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Op {
+    ADD,
+    SUB,
+    MUL,
+    DIV,
+    MOD,
+    LT,
+    LE,
+    GT,
+    GE,
+}
+
+impl Op {
+    fn from_token(token: Token) -> Option<Self> {
+        match token {
+            Token::PLUS => Some(Self::ADD),
+            Token::MINUS => Some(Self::SUB),
+            Token::STAR => Some(Self::MUL),
+            Token::SLASH => Some(Self::DIV),
+            Token::PERCENT => Some(Self::MOD),
+            Token::LT => Some(Self::LT),
+            Token::LE => Some(Self::LE),
+            Token::GT => Some(Self::GT),
+            Token::GE => Some(Self::GE),
+            _ => None,
+        }
+    }
+    pub fn symbol(self) -> &'static str {
+        match self {
+            Self::ADD => "+",
+            Self::SUB => "-",
+            Self::MUL => "*",
+            Self::DIV => "/",
+            Self::MOD => "%",
+            Self::LT => "<",
+            Self::LE => "<=",
+            Self::GT => ">",
+            Self::GE => ">=",
+        }
+    }
+    // Operators of a higher precedence bind tighter.
+    pub fn precedence(self) -> u8 {
+        match self {
+            Self::LT | Self::LE | Self::GT | Self::GE => 0,
+            Self::ADD | Self::SUB => 1,
+            Self::MUL | Self::DIV | Self::MOD => 2,
+        }
+    }
+    pub fn apply(self, left: u8, right: u8) -> u8 {
+        match self {
+            Self::ADD => left.wrapping_add(right),
+            Self::SUB => left.wrapping_sub(right),
+            Self::MUL => left.wrapping_mul(right),
+            Self::DIV => left.checked_div(right).unwrap_or(0),
+            Self::MOD => left.checked_rem(right).unwrap_or(left),
+            Self::LT => (left < right) as u8,
+            Self::LE => (left <= right) as u8,
+            Self::GT => (left > right) as u8,
+            Self::GE => (left >= right) as u8,
         }
-        let variable = Variable::try_from(&tokens[0])?;
-        let num = Num::try_from(&tokens[2])?;
-        match tokens[1] {
-            Token::EQ => Ok(Self::EQ(variable, num)),
-            Token::NE => Ok(Self::NE(variable, num)),
-            _ => Err(anyhow!("Expected == or !=, found {:?}", tokens[1])),
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Expr<'a> {
+    Num(Num),
+    Variable(Variable<'a>),
+    // The element of an array at an index.
+    Element(Variable<'a>, Box<Expr<'a>>),
+    Binary(Op, Box<Expr<'a>>, Box<Expr<'a>>),
+}
+
+// The name of the cell holding the element of `array` at `index`, in the
+// layout of a frame and in the evaluator.
+pub fn element_name(array: &str, index: u8) -> String {
+    format!("{}[{}]", array, index)
+}
+
+impl<'a> Expr<'a> {
+    // The variables read by the expression, from left to right. Arrays are
+    // not variables, the variables of their indexes are.
+    pub fn variables(&self) -> Vec<&Variable<'a>> {
+        match self {
+            Self::Num(_) => vec![],
+            Self::Variable(var) => vec![var],
+            Self::Element(_, index) => index.variables(),
+            Self::Binary(_, left, right) => [left.variables(), right.variables()].concat(),
+        }
+    }
+    // The arrays read by the expression, from left to right.
+    pub fn arrays(&self) -> Vec<&Variable<'a>> {
+        match self {
+            Self::Num(_) | Self::Variable(_) => vec![],
+            Self::Element(array, index) => [vec![array], index.arrays()].concat(),
+            Self::Binary(_, left, right) => [left.arrays(), right.arrays()].concat(),
+        }
+    }
+    // The value of the expression, if `value` knows every variable and element
+    // it reads.
+    pub fn evaluate(&self, value: &impl Fn(&str) -> Option<u8>) -> Option<u8> {
+        match self {
+            Self::Num(num) => Some(num.into()),
+            Self::Variable(var) => value(var),
+            Self::Element(array, index) => value(&element_name(array, index.evaluate(value)?)),
+            Self::Binary(op, left, right) => {
+                Some(op.apply(left.evaluate(value)?, right.evaluate(value)?))
+            }
+        }
+    }
+    pub fn into_owned(self) -> Expr<'static> {
+        match self {
+            Self::Num(num) => Expr::Num(num),
+            Self::Variable(var) => Expr::Variable(var.into_owned()),
+            Self::Element(array, index) => {
+                Expr::Element(array.into_owned(), Box::new(index.into_owned()))
+            }
+            Self::Binary(op, left, right) => Expr::Binary(
+                op,
+                Box::new(left.into_owned()),
+                Box::new(right.into_owned()),
+            ),
         }
     }
 }
@@ -355,23 +682,55 @@ impl From<&Num> for u8 {
     }
 }
 
+impl From<u8> for Num {
+    fn from(val: u8) -> Self {
+        Self(val)
+    }
+}
+
 impl<'a> TryFrom<&Token<'a>> for Num {
     type Error = anyhow::Error;
     fn try_from(token: &Token<'a>) -> Result<Self> {
         let Token::NUM(num) = token else {
             return Err(anyhow!("Expected NUM, found {:?}", token));
         };
-        Ok(Self(num.parse().unwrap()))
+        let num = num
+            .parse()
+            .map_err(|_| anyhow!("{} is not a number between 0 and 255", num))?;
+        Ok(Self(num))
     }
 }
 
+// Borrowed from the source when parsed, owned when built or renamed by a pass.
 #[derive(Debug, PartialEq, Clone)]
-pub struct Variable<'a>(&'a str);
+pub struct Variable<'a>(Cow<'a, str>);
+
+impl<'a> Variable<'a> {
+    // A variable named `name`, if the parser would accept it as one.
+    pub fn new(name: impl Into<Cow<'a, str>>) -> Result<Self> {
+        let name = name.into();
+        match Token::try_from(&*name) {
+            Ok(token @ Token::ID(_)) => Variable::try_from(&token)?,
+            _ => return Err(anyhow!("{} is not a valid variable name", name)),
+        };
+        Ok(Self(name))
+    }
+    pub fn into_owned(self) -> Variable<'static> {
+        Variable(Cow::Owned(self.0.into_owned()))
+    }
+    // The name as a fragment of the source, unless it is owned.
+    pub fn borrowed(&self) -> Option<&'a str> {
+        match self.0 {
+            Cow::Borrowed(name) => Some(name),
+            Cow::Owned(_) => None,
+        }
+    }
+}
 
 impl std::ops::Deref for Variable<'_> {
     type Target = str;
     fn deref(&self) -> &Self::Target {
-        self.0
+        &self.0
     }
 }
 
@@ -384,7 +743,7 @@ impl<'a> TryFrom<&Token<'a>> for Variable<'a> {
         if RESERVED_WORDS.contains(id) {
             return Err(anyhow!("{} is a reserved word", id));
         }
-        Ok(Self(id))
+        Ok(Self(Cow::Borrowed(id)))
     }
 }
 
@@ -408,19 +767,23 @@ impl TryFrom<&Token<'_>> for Direction {
     }
 }
 
-const RESERVED_WORDS: [&str; 7] = [
+const RESERVED_WORDS: [&str; 9] = [
     "if",
     "else",
     "while",
+    "let",
     "next_cell",
     "prev_cell",
     "input",
     "output",
+    "carry",
 ];
 
 #[cfg(test)]
+#[allow(clippy::module_inception)]
 mod parser {
     use super::*;
+    use crate::scanner::TokenStream;
     macro_rules! test_all_cases {
         ($testcases: expr, $type: ty) => {
             for (token, expect) in $testcases.iter() {
@@ -458,7 +821,7 @@ mod parser {
     #[test]
     fn test_parse_variable() {
         let testcase = [
-            (Token::ID("hello"), Ok(Variable("hello"))),
+            (Token::ID("hello"), Ok(Variable("hello".into()))),
             (Token::NUM("123"), Err(())),
         ];
         let reserved_words: Vec<(Token, Result<Variable, ()>)> = RESERVED_WORDS
@@ -484,7 +847,7 @@ mod parser {
         let testcase = [
             (
                 vec![Token::ID("hello"), Token::EQ, Token::NUM("123")],
-                Ok(Compare::EQ(Variable("hello"), Num(123))),
+                Ok(Compare::EQ(Variable("hello".into()), Num(123))),
             ),
             (
                 vec![Token::NUM("123"), Token::EQ, Token::ID("hello")],
@@ -492,7 +855,32 @@ mod parser {
             ),
             (
                 vec![Token::ID("hello"), Token::NE, Token::NUM("123")],
-                Ok(Compare::NE(Variable("hello"), Num(123))),
+                Ok(Compare::NE(Variable("hello".into()), Num(123))),
+            ),
+            (
+                vec![Token::ID("hello"), Token::LE, Token::NUM("123")],
+                Ok(Compare::Order(
+                    Variable("hello".into()),
+                    Op::LE,
+                    Expr::Num(Num(123)),
+                )),
+            ),
+            (
+                vec![Token::ID("hello"), Token::GT, Token::ID("world")],
+                Ok(Compare::Order(
+                    Variable("hello".into()),
+                    Op::GT,
+                    Expr::Variable(Variable("world".into())),
+                )),
+            ),
+            (
+                vec![Token::ID("hello"), Token::EQ, Token::ID("world")],
+                Err(()),
+            ),
+            (vec![Token::ID("hello"), Token::LT, Token::LP], Err(())),
+            (
+                vec![Token::ID("hello"), Token::PLUS, Token::NUM("1")],
+                Err(()),
             ),
         ];
         test_all_cases_vec!(testcase, Compare);
@@ -503,13 +891,13 @@ mod parser {
             (
                 vec![Token::ID("hello"), Token::EQ, Token::NUM("123")],
                 Ok(Bool {
-                    compares: vec![Compare::EQ(Variable("hello"), Num(123))],
+                    compares: vec![Compare::EQ(Variable("hello".into()), Num(123))],
                 }),
             ),
             (
                 vec![Token::ID("hello"), Token::EQ, Token::NUM("123"), Token::LB],
                 Ok(Bool {
-                    compares: vec![Compare::EQ(Variable("hello"), Num(123))],
+                    compares: vec![Compare::EQ(Variable("hello".into()), Num(123))],
                 }),
             ),
             (
@@ -528,8 +916,8 @@ mod parser {
                 ],
                 Ok(Bool {
                     compares: vec![
-                        Compare::EQ(Variable("hello"), Num(123)),
-                        Compare::EQ(Variable("world"), Num(124)),
+                        Compare::EQ(Variable("hello".into()), Num(123)),
+                        Compare::EQ(Variable("world".into()), Num(124)),
                     ],
                 }),
             ),
@@ -541,7 +929,7 @@ mod parser {
         let testcase = [
             (
                 vec![Token::ID("input"), Token::LP, Token::ID("hello"), Token::RP],
-                Ok(Statement::Input(Variable("hello"))),
+                Ok(Statement::Input(Variable("hello".into()))),
             ),
             (
                 vec![Token::ID("input"), Token::LP, Token::NUM("123"), Token::RP],
@@ -560,7 +948,7 @@ mod parser {
                     Token::ID("hello"),
                     Token::RP,
                 ],
-                Ok(Statement::Output(Variable("hello"))),
+                Ok(Statement::Output(Variable("hello".into()))),
             ),
             (
                 vec![Token::ID("output"), Token::LP, Token::NUM("123"), Token::RP],
@@ -570,15 +958,29 @@ mod parser {
         test_all_cases_vec!(testcase, Statement);
     }
     #[test]
+    fn test_parse_carry() {
+        let testcase = [
+            (
+                vec![Token::ID("carry"), Token::LP, Token::ID("state"), Token::RP],
+                Ok(Statement::Carry(Variable("state".into()))),
+            ),
+            (
+                vec![Token::ID("carry"), Token::LP, Token::NUM("123"), Token::RP],
+                Err(()),
+            ),
+        ];
+        test_all_cases_vec!(testcase, Statement);
+    }
+    #[test]
     fn test_parse_move_statement() {
         let testcase = [
             (
                 vec![Token::ID("move_right")],
-                Ok(Statement::Move(Direction::Right)),
+                Ok(Statement::Move(Direction::Right, "move_right".into())),
             ),
             (
                 vec![Token::ID("move_left")],
-                Ok(Statement::Move(Direction::Left)),
+                Ok(Statement::Move(Direction::Left, "move_left".into())),
             ),
             (vec![Token::ID("abcd")], Err(())),
         ];
@@ -589,7 +991,7 @@ mod parser {
         let testcase = [
             (
                 vec![Token::ID("hello"), Token::ASSIGN, Token::NUM("123")],
-                Ok(Statement::Assign(Variable("hello"), Num(123))),
+                Ok(Statement::Assign(Variable("hello".into()), Num(123))),
             ),
             (
                 vec![Token::NUM("123"), Token::ASSIGN, Token::ID("hello")],
@@ -597,19 +999,123 @@ mod parser {
             ),
             (
                 vec![Token::ID("Hello"), Token::ASSIGN, Token::ID("hello")],
+                Ok(Statement::Compute(
+                    Variable("Hello".into()),
+                    Expr::Variable(Variable("hello".into())),
+                )),
+            ),
+            (
+                vec![Token::ID("Hello"), Token::ASSIGN, Token::PLUS],
                 Err(()),
             ),
         ];
         test_all_cases_vec!(testcase, Statement);
     }
     #[test]
+    fn test_parse_expression() {
+        let var = |name: &'static str| Box::new(Expr::Variable(Variable(name.into())));
+        let num = |val: u8| Box::new(Expr::Num(Num(val)));
+        let parse = |source: &'static str| {
+            let tokens = TokenStream::try_from(source).unwrap();
+            Statement::try_from(tokens.tokens())
+        };
+        let expect = Expr::Binary(
+            Op::LT,
+            Box::new(Expr::Binary(
+                Op::SUB,
+                Box::new(Expr::Binary(Op::ADD, var("a"), num(1))),
+                Box::new(Expr::Binary(Op::MUL, var("b"), num(2))),
+            )),
+            Box::new(Expr::Binary(
+                Op::MOD,
+                Box::new(Expr::Binary(Op::SUB, var("c"), num(3))),
+                var("d"),
+            )),
+        );
+        assert_eq!(
+            parse("x = a + 1 - b * 2 < ( c - 3 ) % d").unwrap(),
+            Statement::Compute(Variable("x".into()), expect)
+        );
+        assert_eq!(
+            parse("x = ( ( 7 ) )").unwrap(),
+            Statement::Assign(Variable("x".into()), Num(7))
+        );
+        assert_eq!(
+            parse("x = ( 1 + 2").unwrap_err().to_string(),
+            "Expected an operator or `)`, found end of file"
+        );
+        assert_eq!(
+            parse("x = 1 + * 2").unwrap_err().to_string(),
+            "Expected a number, a variable or `(`, found `*`"
+        );
+    }
+    #[test]
+    fn test_parse_array() {
+        let var = |name: &'static str| Variable(name.into());
+        let parse = |source: &'static str| {
+            let tokens = TokenStream::try_from(source).unwrap();
+            Statement::try_from(tokens.tokens())
+        };
+        assert_eq!(
+            parse("let buf [ 16 ]").unwrap(),
+            Statement::Let(var("buf"), Num(16))
+        );
+        assert_eq!(
+            parse("buf [ i + 1 ] = buf [ buf [ 0 ] ]").unwrap(),
+            Statement::Store(
+                var("buf"),
+                Expr::Binary(
+                    Op::ADD,
+                    Box::new(Expr::Variable(var("i"))),
+                    Box::new(Expr::Num(Num(1)))
+                ),
+                Expr::Element(
+                    var("buf"),
+                    Box::new(Expr::Element(var("buf"), Box::new(Expr::Num(Num(0)))))
+                ),
+            )
+        );
+        assert_eq!(
+            parse("let buf [ 0 ]").unwrap_err().to_string(),
+            "An array has at least one element"
+        );
+        assert_eq!(
+            parse("let buf [ n ]").unwrap_err().to_string(),
+            "Expected a number, found `n`"
+        );
+        assert_eq!(
+            parse("x = buf [ 1 2").unwrap_err().to_string(),
+            "Expected an operator or `]`, found `2`"
+        );
+        assert_eq!(
+            parse("x + 1").unwrap_err().to_string(),
+            "Expected `=` or `[`, found `+`"
+        );
+        let tokens = TokenStream::try_from("x = b [ i ] + c").unwrap();
+        let Statement::Compute(_, expr) = Statement::try_from(tokens.tokens()).unwrap() else {
+            panic!();
+        };
+        assert_eq!(expr.variables(), [&var("i"), &var("c")]);
+        assert_eq!(expr.arrays(), [&var("b")]);
+    }
+    #[test]
+    fn test_apply() {
+        assert_eq!(Op::ADD.apply(200, 100), 44);
+        assert_eq!(Op::SUB.apply(1, 2), 255);
+        assert_eq!(Op::MUL.apply(16, 17), 16);
+        assert_eq!(Op::DIV.apply(200, 7), 28);
+        assert_eq!(Op::MOD.apply(200, 7), 4);
+        assert_eq!(Op::LE.apply(3, 3), 1);
+        assert_eq!(Op::GT.apply(3, 3), 0);
+    }
+    #[test]
     fn test_parse_while() {
         let testcase = [
             (
                 "while abc == 123 { input ( cde ) }",
                 Ok((
-                    vec![Compare::EQ(Variable("abc"), Num(123))],
-                    vec![Statement::Input(Variable("cde"))],
+                    vec![Compare::EQ(Variable("abc".into()), Num(123))],
+                    vec![Statement::Input(Variable("cde".into()))],
                 )),
             ),
             ("while abc == 123 input ( cde ) }", Err(())),
@@ -618,10 +1124,10 @@ mod parser {
                 "while abc == 123 && efg != 124 { input ( hij ) }",
                 Ok((
                     vec![
-                        Compare::EQ(Variable("abc"), Num(123)),
-                        Compare::NE(Variable("efg"), Num(124)),
+                        Compare::EQ(Variable("abc".into()), Num(123)),
+                        Compare::NE(Variable("efg".into()), Num(124)),
                     ],
-                    vec![Statement::Input(Variable("hij"))],
+                    vec![Statement::Input(Variable("hij".into()))],
                 )),
             ),
         ]
@@ -643,8 +1149,8 @@ mod parser {
             (
                 "if abc == 123 { input ( cde ) }",
                 Ok((
-                    vec![Compare::EQ(Variable("abc"), Num(123))],
-                    vec![Statement::Input(Variable("cde"))],
+                    vec![Compare::EQ(Variable("abc".into()), Num(123))],
+                    vec![Statement::Input(Variable("cde".into()))],
                 )),
             ),
             ("if abc == 123 input ( cde ) }", Err(())),
@@ -666,9 +1172,9 @@ mod parser {
             (
                 "if abc == 123 { input ( cde ) } else { output ( fgh ) }",
                 Ok((
-                    vec![Compare::EQ(Variable("abc"), Num(123))],
-                    vec![Statement::Input(Variable("cde"))],
-                    vec![Statement::Output(Variable("fgh"))],
+                    vec![Compare::EQ(Variable("abc".into()), Num(123))],
+                    vec![Statement::Input(Variable("cde".into()))],
+                    vec![Statement::Output(Variable("fgh".into()))],
                 )),
             ),
             (
@@ -713,29 +1219,29 @@ while state != 0 {
 ",
             Ok(AST(Function(vec![Statement::WHILE(
                 Bool {
-                    compares: vec![Compare::NE(Variable("state"), Num(0))],
+                    compares: vec![Compare::NE(Variable("state".into()), Num(0))],
                 },
                 Function(vec![Statement::IF(
                     Bool {
-                        compares: vec![Compare::EQ(Variable("state"), Num(1))],
+                        compares: vec![Compare::EQ(Variable("state".into()), Num(1))],
                     },
                     Function(vec![Statement::IF(
                         Bool {
-                            compares: vec![Compare::EQ(Variable("symbol"), Num(0))],
+                            compares: vec![Compare::EQ(Variable("symbol".into()), Num(0))],
                         },
                         Function(vec![
-                            Statement::Assign(Variable("symbol"), Num(1)),
-                            Statement::Move(Direction::Left),
-                            Statement::Assign(Variable("state"), Num(0)),
+                            Statement::Assign(Variable("symbol".into()), Num(1)),
+                            Statement::Move(Direction::Left, "move_left".into()),
+                            Statement::Assign(Variable("state".into()), Num(0)),
                         ]),
                         Some(Function(vec![Statement::IF(
                             Bool {
-                                compares: vec![Compare::EQ(Variable("symbol"), Num(1))],
+                                compares: vec![Compare::EQ(Variable("symbol".into()), Num(1))],
                             },
                             Function(vec![
-                                Statement::Assign(Variable("symbol"), Num(0)),
-                                Statement::Move(Direction::Right),
-                                Statement::Assign(Variable("state"), Num(0)),
+                                Statement::Assign(Variable("symbol".into()), Num(0)),
+                                Statement::Move(Direction::Right, "move_right".into()),
+                                Statement::Assign(Variable("state".into()), Num(0)),
                             ]),
                             None,
                         )])),
@@ -753,4 +1259,110 @@ while state != 0 {
 
         test_all_cases_vec!(testcase, AST);
     }
+    #[test]
+    fn test_into_owned() {
+        let source = String::from("carry ( s ) while s != 0 { output ( s ) move_left }");
+        let tokens = TokenStream::try_from(source.as_str()).unwrap();
+        let parsed = AST::try_from(tokens.tokens()).unwrap();
+        let owned = parsed.clone().into_owned();
+        assert!(parsed.statements()[1].anchor().is_some());
+        drop(tokens);
+        drop(parsed);
+        drop(source);
+        let owned = std::thread::spawn(move || owned).join().unwrap();
+        assert_eq!(
+            owned.to_string(),
+            "carry ( s )\nwhile s != 0 {\n  output ( s )\n  move_left\n}\n"
+        );
+        assert!(owned
+            .statements()
+            .iter()
+            .all(|stmt| stmt.anchor().is_none()));
+        let Statement::WHILE(_, body) = &owned.statements()[1] else {
+            panic!();
+        };
+        assert!(body.statements().iter().all(|stmt| stmt.anchor().is_none()));
+    }
+    #[test]
+    fn test_new_variable() {
+        assert_eq!(*Variable::new(format!("v{}", 1)).unwrap(), *"v1");
+        assert!(Variable::new("while").is_err());
+        assert!(Variable::new("1v").is_err());
+        assert!(Variable::new("").is_err());
+    }
+    #[test]
+    fn test_parse_errors() {
+        let messages = |source: &str| -> Vec<String> {
+            let tokens = TokenStream::try_from(source).unwrap();
+            match parse(tokens.cursor()) {
+                Ok(_) => vec![],
+                Err(errors) => errors.into_iter().map(|err| err.message).collect(),
+            }
+        };
+        assert_eq!(
+            messages("while x != 0 input ( x ) }"),
+            [
+                "Expected `&&` or `{`, found `input`",
+                "Found `}` without a matching `{`"
+            ]
+        );
+        assert_eq!(
+            messages("x = 300\nif x = 1 { y = 2 } else { y = 3 }\noutput ( if )"),
+            [
+                "300 is not a number between 0 and 255",
+                "Expected a comparison operator, found `=`",
+                "Expected a variable, found the reserved word `if`"
+            ]
+        );
+        assert_eq!(
+            messages("if x == 1 { } else y = 1"),
+            ["Expected `{`, found `y`"]
+        );
+        assert_eq!(
+            messages("while x != 0 { y = 1 else { } }"),
+            ["Found `else` without an `if`"]
+        );
+        assert_eq!(
+            messages("while x != 0 { input x )"),
+            [
+                "Expected `(`, found `x`",
+                "Expected a statement or `}`, found end of file"
+            ]
+        );
+        assert_eq!(
+            messages("input ( x ) 5 { z = 1 } output ( x )"),
+            ["Expected a statement, found `5`"]
+        );
+    }
+    #[test]
+    fn test_located_errors() {
+        let tokens = TokenStream::try_from("x = 1\nwhile x != 0 {\n  x = 0").unwrap();
+        let err = AST::try_from(&tokens).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "3:8: Expected a statement or `}`, found end of file"
+        );
+        let tokens = TokenStream::try_from("carry ( x ) = 1 output ( if )").unwrap();
+        let err = AST::try_from(&tokens).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "1:13: Expected a statement, found `=`\n\
+             1:26: Expected a variable, found the reserved word `if`"
+        );
+    }
+    #[test]
+    fn test_recovered_statements() {
+        // The statements around an error still parse.
+        let tokens = TokenStream::try_from("x = 1 y = = 2 output ( x )").unwrap();
+        let mut parser = Parser::new(tokens.cursor());
+        let ast = parser.program();
+        assert_eq!(parser.errors.len(), 1);
+        assert_eq!(
+            ast.statements(),
+            [
+                Statement::Assign(Variable("x".into()), Num(1)),
+                Statement::Output(Variable("x".into()))
+            ]
+        );
+    }
 }
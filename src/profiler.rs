@@ -0,0 +1,186 @@
+// Execution profile of a compiled program.
+//
+// Every executed Brainfuck instruction is attributed to the assembly line it
+// was assembled from, and through the `# stmt` markers of that line to the bfc
+// statements enclosing it. A statement's own steps exclude the ones of the
+// statements nested in its bodies; code outside of any statement, such as the
+// tape origin shift, is attributed to no statement.
+
+use crate::scanner::Location;
+use crate::sourcemap::enclosing_statements;
+use anyhow::Result;
+use std::collections::HashMap;
+use std::fmt::Display;
+
+#[derive(Debug, PartialEq)]
+pub struct StatementProfile {
+    pub location: Option<Location>,
+    pub text: String,
+    pub own_steps: u64,
+    pub total_steps: u64,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct InstructionProfile {
+    pub line: usize,
+    pub text: String,
+    pub steps: u64,
+}
+
+// Both lists are sorted from the hottest entry down and only hold entries that
+// were executed at least once.
+#[derive(Debug, PartialEq)]
+pub struct Profile {
+    pub steps: u64,
+    pub statements: Vec<StatementProfile>,
+    pub instructions: Vec<InstructionProfile>,
+}
+
+impl Profile {
+    // `lines` maps every character of the program to its line in `asm`, and
+    // `counts` holds the number of times every character was executed.
+    pub fn new(source: &str, asm: &str, lines: &[usize], counts: &[u64]) -> Result<Self> {
+        let mut line_steps = HashMap::<usize, u64>::new();
+        for (line, count) in lines.iter().zip(counts) {
+            *line_steps.entry(*line).or_default() += count;
+        }
+        let enclosing = enclosing_statements(asm)?;
+        let mut statements = HashMap::<Option<Location>, (u64, u64)>::new();
+        for (line, steps) in line_steps.iter() {
+            let stack = &enclosing[*line];
+            statements.entry(stack.last().copied()).or_default().0 += steps;
+            for location in stack {
+                statements.entry(Some(*location)).or_default().1 += steps;
+            }
+            if stack.is_empty() {
+                statements.entry(None).or_default().1 += steps;
+            }
+        }
+        let source_lines = source.lines().collect::<Vec<_>>();
+        let mut statements = statements
+            .into_iter()
+            .filter(|(_, (own_steps, _))| *own_steps > 0)
+            .map(|(location, (own_steps, total_steps))| StatementProfile {
+                location,
+                text: match location {
+                    Some(location) => source_lines[location.line - 1].trim().to_string(),
+                    None => "(outside of any statement)".to_string(),
+                },
+                own_steps,
+                total_steps,
+            })
+            .collect::<Vec<_>>();
+        statements.sort_by_key(|s| (std::cmp::Reverse(s.own_steps), s.location));
+        let asm_lines = asm.split('\n').collect::<Vec<_>>();
+        let mut instructions = line_steps
+            .into_iter()
+            .filter(|(_, steps)| *steps > 0)
+            .map(|(line, steps)| InstructionProfile {
+                line: line + 1,
+                text: asm_lines[line].to_string(),
+                steps,
+            })
+            .collect::<Vec<_>>();
+        instructions.sort_by_key(|i| (std::cmp::Reverse(i.steps), i.line));
+        Ok(Self {
+            steps: counts.iter().sum(),
+            statements,
+            instructions,
+        })
+    }
+    fn percent(&self, steps: u64) -> f64 {
+        if self.steps == 0 {
+            0.0
+        } else {
+            steps as f64 * 100.0 / self.steps as f64
+        }
+    }
+}
+
+impl Display for Profile {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "steps: {}", self.steps)?;
+        writeln!(f)?;
+        writeln!(
+            f,
+            "{:>12} {:>7} {:>12}  {:<9} statement",
+            "own", "", "total", "location"
+        )?;
+        for s in &self.statements {
+            let location = s.location.map_or("-".to_string(), |l| l.to_string());
+            writeln!(
+                f,
+                "{:>12} {:>6.2}% {:>12}  {:<9} {}",
+                s.own_steps,
+                self.percent(s.own_steps),
+                s.total_steps,
+                location,
+                s.text
+            )?;
+        }
+        writeln!(f)?;
+        writeln!(
+            f,
+            "{:>12} {:>7}  {:<9} instruction",
+            "steps", "", "asm line"
+        )?;
+        for i in &self.instructions {
+            writeln!(
+                f,
+                "{:>12} {:>6.2}%  {:<9} {}",
+                i.steps,
+                self.percent(i.steps),
+                i.line,
+                i.text
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::module_inception)]
+mod profiler {
+    use super::*;
+    use crate::assembler::assemble_with_map;
+    use crate::interpreter::Interpreter;
+
+    fn run(source: &str, asm: &str) -> Profile {
+        let (program, lines) = assemble_with_map(asm).unwrap();
+        let mut interpreter = Interpreter::new(&program).unwrap();
+        interpreter.profile();
+        interpreter.run(&mut [].as_slice(), &mut vec![]).unwrap();
+        Profile::new(source, asm, &lines, interpreter.counts().unwrap()).unwrap()
+    }
+    #[test]
+    fn test_statements() {
+        let source = "x = 3\nwhile x != 0 {\n  x = 0\n}";
+        let asm = "#define x 0\n\
+                   rs 1\n\
+                   # stmt 1:1\nadd x 3\n# end stmt\n\
+                   # stmt 2:7\nloop x\n# stmt 3:3\nsub x 3\n# end stmt\nend x\n# end stmt";
+        let profile = run(source, asm);
+        assert_eq!(profile.steps, 9);
+        let statements = profile
+            .statements
+            .iter()
+            .map(|s| (s.location.map(|l| l.line), s.own_steps, s.total_steps))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            statements,
+            vec![
+                (Some(1), 3, 3),
+                (Some(3), 3, 3),
+                (Some(2), 2, 5),
+                (None, 1, 1)
+            ]
+        );
+        assert_eq!(profile.statements[1].text, "x = 0");
+        let instructions = profile
+            .instructions
+            .iter()
+            .map(|i| (i.line, i.steps))
+            .collect::<Vec<_>>();
+        assert_eq!(instructions, vec![(4, 3), (9, 3), (2, 1), (7, 1), (11, 1)]);
+    }
+}
@@ -0,0 +1,334 @@
+// Random bfc programs for property tests and fuzzing.
+//
+// Programs are generated as ASTs from a stream of bytes, so that the fuzzer
+// can drive the generator directly. They are rendered as source text so that
+// the scanner and parser are exercised as well. Every program terminates and
+// stays within a bounded part of the tape:
+// - every loop has a variable of its own that is only written by the `input`
+//   ending its body, so each iteration consumes a byte of input or exits;
+// - the bodies of `if` and `while` bring the head back to the frame they
+//   started in, so the head movement analysis is exact;
+// - the right operands of `*`, `/` and `%` are constants, which are lowered
+//   to loops over the left operand only, and include 0 for division by zero;
+// - the array, when declared, is indexed by constants within it and by
+//   variables, whose values reach past its end;
+// - declarations are placed either first or in a branch whose condition is
+//   known to be false, which the optimizer drops.
+
+use crate::parser::{Bool, Compare, Direction, Expr, Function, Num, Op, Statement, Variable, AST};
+use std::borrow::Cow;
+
+const VARIABLES: [&str; 4] = ["a", "b", "c", "r"];
+const VALUES: [u8; 5] = [0, 1, 2, 97, 255];
+const ARRAY: &str = "t";
+const ARRAY_LEN: u8 = 3;
+// Guards the branches holding declarations.
+const GUARD: &str = "d";
+const OPERATORS: [Op; 9] = [
+    Op::ADD,
+    Op::SUB,
+    Op::MUL,
+    Op::DIV,
+    Op::MOD,
+    Op::LT,
+    Op::LE,
+    Op::GT,
+    Op::GE,
+];
+const COMPARISONS: [Op; 4] = [Op::LT, Op::LE, Op::GT, Op::GE];
+const MAX_DEPTH: usize = 3;
+const MAX_STATEMENTS: u8 = 4;
+const MAX_TOP_LEVEL_STATEMENTS: u8 = 10;
+
+// Bytes driving the generator; once they run out every choice is zero.
+pub struct Entropy<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Entropy<'a> {
+    pub fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes }
+    }
+    fn choose(&mut self, count: u8) -> u8 {
+        let Some((byte, rest)) = self.bytes.split_first() else {
+            return 0;
+        };
+        self.bytes = rest;
+        byte % count
+    }
+    // The bytes the generator has not consumed.
+    pub fn rest(&self) -> &'a [u8] {
+        self.bytes
+    }
+}
+
+// The names the generator uses are all valid variables.
+fn named(name: String) -> Variable<'static> {
+    Variable::new(name).expect("generated names are valid variables")
+}
+
+fn boxed(expr: Expr<'static>) -> Box<Expr<'static>> {
+    Box::new(expr)
+}
+
+struct Generator<'e, 'a> {
+    entropy: &'e mut Entropy<'a>,
+    loops: usize,
+    array: bool,
+}
+
+impl Generator<'_, '_> {
+    fn variable(&mut self) -> Variable<'static> {
+        let name = VARIABLES[self.entropy.choose(VARIABLES.len() as u8) as usize];
+        named(name.to_string())
+    }
+    fn value(&mut self) -> Num {
+        Num::from(VALUES[self.entropy.choose(VALUES.len() as u8) as usize])
+    }
+    fn operand(&mut self) -> Expr<'static> {
+        if self.entropy.choose(2) == 0 {
+            Expr::Variable(self.variable())
+        } else {
+            Expr::Num(self.value())
+        }
+    }
+    fn index(&mut self) -> Expr<'static> {
+        if self.entropy.choose(2) == 0 {
+            Expr::Variable(self.variable())
+        } else {
+            Expr::Num(Num::from(self.entropy.choose(ARRAY_LEN)))
+        }
+    }
+    // An operand of an expression, which unlike one of a comparison may also
+    // be an element.
+    fn term(&mut self) -> Expr<'static> {
+        if self.array && self.entropy.choose(3) == 0 {
+            Expr::Element(named(ARRAY.to_string()), boxed(self.index()))
+        } else {
+            self.operand()
+        }
+    }
+    // One or two operators, so the loops lowering them stay short.
+    fn expression(&mut self, nested: bool) -> Expr<'static> {
+        let left = self.term();
+        let op = OPERATORS[self.entropy.choose(OPERATORS.len() as u8) as usize];
+        let right = match op {
+            Op::MUL | Op::DIV | Op::MOD => Expr::Num(self.value()),
+            _ => self.term(),
+        };
+        if nested || self.entropy.choose(2) == 0 {
+            Expr::Binary(op, boxed(left), boxed(right))
+        } else {
+            let op = if self.entropy.choose(2) == 0 {
+                Op::ADD
+            } else {
+                Op::SUB
+            };
+            Expr::Binary(op, boxed(self.expression(true)), boxed(left))
+        }
+    }
+    fn compare(&mut self, var: Variable<'static>) -> Compare<'static> {
+        match self.entropy.choose(4) {
+            0 => Compare::EQ(var, self.value()),
+            1 => Compare::NE(var, self.value()),
+            _ => {
+                let op = COMPARISONS[self.entropy.choose(4) as usize];
+                Compare::Order(var, op, self.operand())
+            }
+        }
+    }
+    fn compares(&mut self) -> Vec<Compare<'static>> {
+        let count = 1 + self.entropy.choose(2);
+        (0..count)
+            .map(|_| {
+                let var = self.variable();
+                self.compare(var)
+            })
+            .collect()
+    }
+    // A declaration at the start of the program, possibly in a dead branch.
+    fn declaration(&mut self, stmt: Statement<'static>) -> Vec<Statement<'static>> {
+        let guard = named(GUARD.to_string());
+        match self.entropy.choose(3) {
+            0 => vec![stmt],
+            1 => vec![
+                Statement::Assign(guard.clone(), Num::from(1)),
+                Statement::IF(
+                    Bool::new(vec![Compare::EQ(guard, Num::from(2))]),
+                    Function::new(vec![stmt]),
+                    None,
+                ),
+            ],
+            _ => vec![
+                Statement::Assign(guard.clone(), Num::from(0)),
+                Statement::WHILE(
+                    Bool::new(vec![Compare::NE(guard, Num::from(0))]),
+                    Function::new(vec![stmt]),
+                ),
+            ],
+        }
+    }
+    // A block of statements together with the frames it moves the head by.
+    fn block(&mut self, depth: usize) -> (Vec<Statement<'static>>, i64) {
+        let max = if depth == 0 {
+            MAX_TOP_LEVEL_STATEMENTS
+        } else {
+            MAX_STATEMENTS
+        };
+        let count = self.entropy.choose(max + 1);
+        let mut statements = vec![];
+        let mut net = 0;
+        for _ in 0..count {
+            net += self.statement(depth, &mut statements);
+        }
+        (statements, net)
+    }
+    // A block that brings the head back to the frame it started in.
+    fn balanced_block(&mut self, depth: usize) -> Vec<Statement<'static>> {
+        let (mut statements, net) = self.block(depth);
+        let back = if net > 0 {
+            Statement::Move(Direction::Left, Cow::Borrowed("move_left"))
+        } else {
+            Statement::Move(Direction::Right, Cow::Borrowed("move_right"))
+        };
+        for _ in 0..net.abs() {
+            statements.push(back.clone());
+        }
+        statements
+    }
+    fn statement(&mut self, depth: usize, statements: &mut Vec<Statement<'static>>) -> i64 {
+        let kinds = if depth < MAX_DEPTH { 9 } else { 7 };
+        match self.entropy.choose(kinds) {
+            0 | 1 => {
+                let var = self.variable();
+                statements.push(Statement::Assign(var, self.value()));
+            }
+            2 => statements.push(Statement::Input(self.variable())),
+            3 => statements.push(Statement::Output(self.variable())),
+            4 => {
+                statements.push(Statement::Move(
+                    Direction::Right,
+                    Cow::Borrowed("move_right"),
+                ));
+                return 1;
+            }
+            5 => {
+                statements.push(Statement::Move(Direction::Left, Cow::Borrowed("move_left")));
+                return -1;
+            }
+            6 => {
+                if self.array && self.entropy.choose(2) == 0 {
+                    let index = self.index();
+                    let expr = self.expression(false);
+                    statements.push(Statement::Store(named(ARRAY.to_string()), index, expr));
+                } else {
+                    let var = self.variable();
+                    statements.push(Statement::Compute(var, self.expression(false)));
+                }
+            }
+            7 => {
+                let cond = Bool::new(self.compares());
+                let if_func = Function::new(self.balanced_block(depth + 1));
+                let else_func = if self.entropy.choose(2) == 0 {
+                    Some(Function::new(self.balanced_block(depth + 1)))
+                } else {
+                    None
+                };
+                statements.push(Statement::IF(cond, if_func, else_func));
+            }
+            _ => {
+                let var = named(format!("l{}", self.loops));
+                self.loops += 1;
+                let mut compares = vec![Compare::NE(var.clone(), Num::from(0))];
+                if self.entropy.choose(2) == 0 {
+                    compares.extend(self.compares());
+                }
+                let mut body = self.balanced_block(depth + 1);
+                body.push(Statement::Input(var.clone()));
+                statements.push(Statement::Input(var));
+                statements.push(Statement::WHILE(Bool::new(compares), Function::new(body)));
+            }
+        }
+        0
+    }
+}
+
+// A terminating program chosen by the bytes of `entropy`.
+pub fn random_ast(entropy: &mut Entropy) -> AST<'static> {
+    let mut generator = Generator {
+        entropy,
+        loops: 0,
+        array: false,
+    };
+    let mut statements = vec![];
+    // One byte for both declarations, and one more for where each is placed.
+    let declarations = generator.entropy.choose(4);
+    if declarations & 1 == 0 {
+        let carry = Statement::Carry(named("r".to_string()));
+        statements.extend(generator.declaration(carry));
+    }
+    if declarations & 2 != 0 {
+        generator.array = true;
+        let array = Statement::Let(named(ARRAY.to_string()), Num::from(ARRAY_LEN));
+        statements.extend(generator.declaration(array));
+    }
+    let (block, _) = generator.block(0);
+    statements.extend(block);
+    AST::new(Function::new(statements))
+}
+
+// The source text of `random_ast`.
+pub fn random_program(entropy: &mut Entropy) -> String {
+    random_ast(entropy).to_string()
+}
+
+#[cfg(test)]
+#[allow(clippy::module_inception)]
+mod random {
+    use super::*;
+    use crate::compiler::differential;
+    use crate::scanner::TokenStream;
+
+    // xorshift64, enough to spread the seeds over the generator's choices.
+    fn bytes(seed: u64, len: usize) -> Vec<u8> {
+        let mut state = seed.wrapping_mul(0x9e3779b97f4a7c15) | 1;
+        (0..len)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                (state >> 32) as u8
+            })
+            .collect()
+    }
+    #[test]
+    fn test_entropy() {
+        let mut entropy = Entropy::new(&[7, 9]);
+        assert_eq!(entropy.choose(4), 3);
+        assert_eq!(entropy.rest(), &[9]);
+        assert_eq!(entropy.choose(4), 1);
+        assert_eq!(entropy.choose(4), 0);
+    }
+    #[test]
+    fn test_render() {
+        for seed in 0..100 {
+            let bytes = bytes(seed, 256);
+            let ast = random_ast(&mut Entropy::new(&bytes));
+            let source = ast.to_string();
+            let tokens = TokenStream::try_from(source.as_str()).unwrap();
+            assert_eq!(AST::try_from(&tokens).unwrap(), ast, "{}", source);
+        }
+    }
+    #[test]
+    fn test_random_programs() {
+        for seed in 0..300 {
+            let bytes = bytes(seed, 256);
+            let mut entropy = Entropy::new(&bytes);
+            let program = random_program(&mut entropy);
+            let input = &entropy.rest()[..8.min(entropy.rest().len())];
+            let differences = differential(&program, input, 10_000_000)
+                .unwrap_or_else(|err| panic!("{}\n{}", err, program));
+            assert!(differences.is_empty(), "{:?}\n{}", differences, program);
+        }
+    }
+}
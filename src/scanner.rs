@@ -1,7 +1,8 @@
-use anyhow::Result;
+use anyhow::{anyhow, Result};
+use std::fmt::Display;
+use std::str::FromStr;
 
-
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone, Copy)]
 pub enum Token<'a> {
     ID(&'a str),
     NUM(&'a str),
@@ -12,25 +13,177 @@ pub enum Token<'a> {
     RB,
     LP,
     RP,
+    LSB,
+    RSB,
     AND,
+    PLUS,
+    MINUS,
+    STAR,
+    SLASH,
+    PERCENT,
+    LT,
+    LE,
+    GT,
+    GE,
     EOF,
 }
 
+// The text of the token, as in the source.
+impl Display for Token<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let text = match self {
+            Token::ID(text) | Token::NUM(text) => text,
+            Token::ASSIGN => "=",
+            Token::EQ => "==",
+            Token::NE => "!=",
+            Token::LB => "{",
+            Token::RB => "}",
+            Token::LP => "(",
+            Token::RP => ")",
+            Token::LSB => "[",
+            Token::RSB => "]",
+            Token::AND => "&&",
+            Token::PLUS => "+",
+            Token::MINUS => "-",
+            Token::STAR => "*",
+            Token::SLASH => "/",
+            Token::PERCENT => "%",
+            Token::LT => "<",
+            Token::LE => "<=",
+            Token::GT => ">",
+            Token::GE => ">=",
+            Token::EOF => "",
+        };
+        write!(f, "{}", text)
+    }
+}
+
 #[derive(Debug)]
 pub struct TokenStream<'a> {
     tokens: Vec<Token<'a>>,
+    // The span of every token, EOF included.
+    spans: Vec<Span>,
 }
 
 impl<'a> TokenStream<'a> {
+    pub fn tokens(&self) -> &[Token<'a>] {
+        &self.tokens
+    }
     pub fn into_tokens(self) -> Vec<Token<'a>> {
         self.tokens
     }
+    pub fn cursor(&self) -> Cursor<'_, 'a> {
+        Cursor {
+            tokens: &self.tokens,
+            spans: &self.spans,
+            position: 0,
+        }
+    }
+}
+
+// Where a token is in the source. EOF is an empty span after the last
+// character.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Span {
+    pub location: Location,
+    // In characters.
+    pub length: usize,
+}
+
+// A position in a sequence of tokens. Tokens without spans, such as the
+// vectors of tests, can be read as well; they are just not located.
+#[derive(Debug, Clone)]
+pub struct Cursor<'t, 'a> {
+    tokens: &'t [Token<'a>],
+    spans: &'t [Span],
+    position: usize,
+}
+
+impl<'t, 'a> From<&'t [Token<'a>]> for Cursor<'t, 'a> {
+    fn from(tokens: &'t [Token<'a>]) -> Self {
+        Self {
+            tokens,
+            spans: &[],
+            position: 0,
+        }
+    }
+}
+
+impl<'a> Cursor<'_, 'a> {
+    // The token `offset` tokens ahead. Tokens that do not end with EOF are
+    // read as if they did.
+    pub fn peek_at(&self, offset: usize) -> Token<'a> {
+        self.tokens
+            .get(self.position + offset)
+            .copied()
+            .unwrap_or(Token::EOF)
+    }
+    pub fn peek(&self) -> Token<'a> {
+        self.peek_at(0)
+    }
+    pub fn advance(&mut self) -> Token<'a> {
+        let token = self.peek();
+        self.position += 1;
+        token
+    }
+    // The number of tokens read so far.
+    pub fn position(&self) -> usize {
+        self.position
+    }
+    // The span of the next token.
+    pub fn span(&self) -> Option<Span> {
+        let last = self.spans.len().checked_sub(1)?;
+        self.spans.get(self.position.min(last)).copied()
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy)]
+pub struct Location {
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Location {
+    // Tokens borrow their text from the source, so a token (or anything parsed
+    // from it) can be located by its address within the source.
+    pub fn find(source: &str, fragment: &str) -> Option<Location> {
+        let offset = (fragment.as_ptr() as usize).checked_sub(source.as_ptr() as usize)?;
+        if offset + fragment.len() > source.len() {
+            return None;
+        }
+        let before = &source[..offset];
+        let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+        Some(Location {
+            line: before.matches('\n').count() + 1,
+            column: before[line_start..].chars().count() + 1,
+        })
+    }
+}
+
+impl Display for Location {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.line, self.column)
+    }
+}
+
+impl FromStr for Location {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self> {
+        let (line, column) = s
+            .split_once(':')
+            .ok_or_else(|| anyhow!("Expected line:column, found {}", s))?;
+        Ok(Location {
+            line: line.parse()?,
+            column: column.parse()?,
+        })
+    }
 }
 
 macro_rules! match_regex {
     ($regex: expr, $type: expr) => {
         |program| -> Option<Token> {
-            let re = regex::Regex::new($regex).unwrap();
+            static RE: std::sync::OnceLock<regex::Regex> = std::sync::OnceLock::new();
+            let re = RE.get_or_init(|| regex::Regex::new($regex).unwrap());
             if re.is_match(program) {
                 Some($type(program))
             } else {
@@ -56,7 +209,7 @@ impl<'a> TryFrom<&'a str> for Token<'a> {
     type Error = anyhow::Error;
     fn try_from(program: &'a str) -> Result<Self> {
         let match_func = [
-            match_regex!(r"^[a-zA-z_][a-zA-Z_0-9]*$", Token::ID),
+            match_regex!(r"^[a-zA-Z_][a-zA-Z_0-9]*$", Token::ID),
             match_regex!(r"^[0-9]+$", Token::NUM),
             match_str!("=", Token::ASSIGN),
             match_str!("==", Token::EQ),
@@ -65,7 +218,18 @@ impl<'a> TryFrom<&'a str> for Token<'a> {
             match_str!("}", Token::RB),
             match_str!("(", Token::LP),
             match_str!(")", Token::RP),
+            match_str!("[", Token::LSB),
+            match_str!("]", Token::RSB),
             match_str!("&&", Token::AND),
+            match_str!("+", Token::PLUS),
+            match_str!("-", Token::MINUS),
+            match_str!("*", Token::STAR),
+            match_str!("/", Token::SLASH),
+            match_str!("%", Token::PERCENT),
+            match_str!("<", Token::LT),
+            match_str!("<=", Token::LE),
+            match_str!(">", Token::GT),
+            match_str!(">=", Token::GE),
         ];
         for func in match_func.iter() {
             if let Some(token) = func(program) {
@@ -79,15 +243,44 @@ impl<'a> TryFrom<&'a str> for Token<'a> {
 impl<'a> TryFrom<&'a str> for TokenStream<'a> {
     type Error = anyhow::Error;
     fn try_from(program: &'a str) -> Result<Self> {
-        let tokens = program
-            .split_whitespace()
-            .map(|token| {
-                
-                Token::try_from(token)
-            })
-            .chain(std::iter::once(Ok(Token::EOF)))
-            .collect::<Result<Vec<Token>>>()?;
-        Ok(Self { tokens })
+        let mut tokens = vec![];
+        let mut spans = vec![];
+        let mut location = Location { line: 1, column: 1 };
+        let mut start = None;
+        for (i, c) in program
+            .char_indices()
+            .chain(std::iter::once((program.len(), ' ')))
+        {
+            if !c.is_whitespace() {
+                start.get_or_insert((i, location));
+            } else if let Some((begin, begin_location)) = start.take() {
+                let word = &program[begin..i];
+                let token = Token::try_from(word)
+                    .map_err(|_| anyhow!("{}: Invalid token {}", begin_location, word))?;
+                tokens.push(token);
+                spans.push(Span {
+                    location: begin_location,
+                    length: word.chars().count(),
+                });
+            }
+            if c == '\n' {
+                location = Location {
+                    line: location.line + 1,
+                    column: 1,
+                };
+            } else {
+                location.column += 1;
+            }
+        }
+        tokens.push(Token::EOF);
+        spans.push(Span {
+            location: Location {
+                column: location.column - 1,
+                ..location
+            },
+            length: 0,
+        });
+        Ok(Self { tokens, spans })
     }
 }
 
@@ -114,6 +307,8 @@ mod token {
             ("_hello", true),
             ("HELLO", true),
             ("1hello", false),
+            ("[", false),
+            ("a^b", false),
         ];
         for (program, is_match) in testcases.into_iter() {
             test_token!(program, is_match, Token::ID);
@@ -188,6 +383,41 @@ mod token {
         assert_eq!(output, expect);
     }
     #[test]
+    fn test_operators() {
+        let program = "[ ] + - * / % < <= > >=";
+        let expect = [
+            Token::LSB,
+            Token::RSB,
+            Token::PLUS,
+            Token::MINUS,
+            Token::STAR,
+            Token::SLASH,
+            Token::PERCENT,
+            Token::LT,
+            Token::LE,
+            Token::GT,
+            Token::GE,
+        ];
+        for (word, token) in program.split(' ').zip(expect) {
+            assert_eq!(Token::try_from(word).unwrap(), token);
+            assert_eq!(token.to_string(), word);
+        }
+        assert!(Token::try_from("=<").is_err());
+    }
+    #[test]
+    fn test_location() {
+        let program = "x = 1\n  input ( x )";
+        let fragment = &program[16..17];
+        let expect = Location {
+            line: 2,
+            column: 11,
+        };
+        assert_eq!(Location::find(program, fragment), Some(expect));
+        assert_eq!(Location::find(program, &String::from("x")), None);
+        assert_eq!("2:11".parse::<Location>().unwrap(), expect);
+        assert!("2".parse::<Location>().is_err());
+    }
+    #[test]
     fn test_token_stream() {
         let program = "
             if hello != 123 && world == 456 {
@@ -217,4 +447,52 @@ mod token {
         let output = TokenStream::try_from(program).unwrap();
         assert_eq!(output.tokens, expect);
     }
+    #[test]
+    fn test_spans() {
+        let program = "x = 1\n  input\u{3000}( x )\n";
+        let stream = TokenStream::try_from(program).unwrap();
+        let at = |line, column, length| Span {
+            location: Location { line, column },
+            length,
+        };
+        assert_eq!(
+            stream.spans,
+            [
+                at(1, 1, 1),
+                at(1, 3, 1),
+                at(1, 5, 1),
+                at(2, 3, 5),
+                at(2, 9, 1),
+                at(2, 11, 1),
+                at(2, 13, 1),
+                at(3, 1, 0)
+            ]
+        );
+        let err = TokenStream::try_from("x = 1\n  ? = 2").unwrap_err();
+        assert_eq!(err.to_string(), "2:3: Invalid token ?");
+    }
+    #[test]
+    fn test_cursor() {
+        let stream = TokenStream::try_from("x = 1").unwrap();
+        let mut cursor = stream.cursor();
+        assert_eq!(cursor.advance(), Token::ID("x"));
+        assert_eq!(cursor.peek_at(1), Token::NUM("1"));
+        assert_eq!(
+            cursor.span().unwrap().location,
+            Location { line: 1, column: 3 }
+        );
+        cursor.advance();
+        cursor.advance();
+        cursor.advance();
+        assert_eq!(cursor.peek(), Token::EOF);
+        assert_eq!(
+            cursor.span().unwrap().location,
+            Location { line: 1, column: 6 }
+        );
+
+        let tokens = [Token::ID("x")];
+        let mut cursor = Cursor::from(&tokens[..]);
+        cursor.advance();
+        assert_eq!((cursor.peek(), cursor.span()), (Token::EOF, None));
+    }
 }
@@ -0,0 +1,167 @@
+// Source maps from Brainfuck back to the assembly and the bfc source.
+//
+// The program is split into ranges of characters assembled from the same
+// assembly instruction. Every range records the index of that instruction,
+// counting only instructions (no defines, comments or empty lines), and the
+// location of the innermost statement enclosing it according to the
+// `# stmt` markers of the assembly.
+
+use crate::scanner::Location;
+use anyhow::Result;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Mapping {
+    pub start: usize,
+    pub end: usize,
+    pub instruction: usize,
+    pub location: Option<Location>,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct SourceMap {
+    pub mappings: Vec<Mapping>,
+}
+
+// The statements enclosing every line of `asm`, innermost last.
+pub fn enclosing_statements(asm: &str) -> Result<Vec<Vec<Location>>> {
+    let mut stack = vec![];
+    let mut enclosing = vec![];
+    for command in asm.split('\n') {
+        if let Some(location) = command.strip_prefix("# stmt ") {
+            stack.push(location.parse()?);
+        }
+        enclosing.push(stack.clone());
+        if command == "# end stmt" {
+            stack.pop();
+        }
+    }
+    Ok(enclosing)
+}
+
+// Index of every line of `asm` among the instructions, `None` for lines that
+// are not instructions.
+fn instruction_indices(asm: &str) -> Vec<Option<usize>> {
+    let mut next = 0;
+    asm.split('\n')
+        .map(|command| {
+            if command.is_empty() || command.starts_with('#') {
+                None
+            } else {
+                next += 1;
+                Some(next - 1)
+            }
+        })
+        .collect()
+}
+
+impl SourceMap {
+    // `lines` maps every character of the program to its line in `asm`, as
+    // returned by `assemble_with_map`.
+    pub fn new(asm: &str, lines: &[usize]) -> Result<Self> {
+        let enclosing = enclosing_statements(asm)?;
+        let instructions = instruction_indices(asm);
+        let mut mappings: Vec<Mapping> = vec![];
+        for (i, line) in lines.iter().enumerate() {
+            if let Some(last) = mappings.last_mut() {
+                if last.end == i && lines[last.start] == *line {
+                    last.end += 1;
+                    continue;
+                }
+            }
+            mappings.push(Mapping {
+                start: i,
+                end: i + 1,
+                instruction: instructions[*line].unwrap_or_default(),
+                location: enclosing[*line].last().copied(),
+            });
+        }
+        Ok(Self { mappings })
+    }
+    // The mapping of the character at `offset` of the program.
+    pub fn find(&self, offset: usize) -> Option<&Mapping> {
+        let i = self.mappings.partition_point(|m| m.end <= offset);
+        self.mappings.get(i).filter(|m| m.start <= offset)
+    }
+    // Ranges are half-open character offsets; `line` and `column` are null for
+    // code outside of any statement.
+    pub fn to_json(&self) -> String {
+        let mappings = self
+            .mappings
+            .iter()
+            .map(|m| {
+                let (line, column) = match m.location {
+                    Some(location) => (location.line.to_string(), location.column.to_string()),
+                    None => ("null".to_string(), "null".to_string()),
+                };
+                format!(
+                    "    {{\"start\": {}, \"end\": {}, \"instruction\": {}, \"line\": {}, \"column\": {}}}",
+                    m.start, m.end, m.instruction, line, column
+                )
+            })
+            .collect::<Vec<_>>()
+            .join(",\n");
+        format!(
+            "{{\n  \"version\": 1,\n  \"mappings\": [\n{}\n  ]\n}}\n",
+            mappings
+        )
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::module_inception)]
+mod sourcemap {
+    use super::*;
+    use crate::assembler::assemble_with_map;
+
+    fn source_map(asm: &str) -> SourceMap {
+        let (_, lines) = assemble_with_map(asm).unwrap();
+        SourceMap::new(asm, &lines).unwrap()
+    }
+    #[test]
+    fn test_mappings() {
+        let asm = "#define x 1\nrs 1\n# stmt 2:3\nadd x 2\nwrite x\n# end stmt";
+        let location = Some(Location { line: 2, column: 3 });
+        let expect = vec![
+            Mapping {
+                start: 0,
+                end: 1,
+                instruction: 0,
+                location: None,
+            },
+            Mapping {
+                start: 1,
+                end: 5,
+                instruction: 1,
+                location,
+            },
+            Mapping {
+                start: 5,
+                end: 8,
+                instruction: 2,
+                location,
+            },
+        ];
+        let map = source_map(asm);
+        assert_eq!(map.mappings, expect);
+        assert_eq!(map.find(6), Some(&expect[2]));
+        assert_eq!(map.find(8), None);
+    }
+    #[test]
+    fn test_nested_statements() {
+        let asm = "# stmt 1:1\nloop 0\n# stmt 2:3\nread 0\n# end stmt\nend 0\n# end stmt";
+        let locations = source_map(asm)
+            .mappings
+            .iter()
+            .map(|m| m.location.unwrap().line)
+            .collect::<Vec<_>>();
+        assert_eq!(locations, vec![1, 2, 1]);
+    }
+    #[test]
+    fn test_json() {
+        let asm = "rs 1\n# stmt 2:3\nadd 0 1\n# end stmt";
+        let expect = "{\n  \"version\": 1,\n  \"mappings\": [\n    \
+            {\"start\": 0, \"end\": 1, \"instruction\": 0, \"line\": null, \"column\": null},\n    \
+            {\"start\": 1, \"end\": 2, \"instruction\": 1, \"line\": 2, \"column\": 3}\n  ]\n}\n";
+        assert_eq!(source_map(asm).to_json(), expect);
+    }
+}
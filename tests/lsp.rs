@@ -0,0 +1,93 @@
+// A session with the language server: the requests are written to its stdin
+// up front and the responses read back once it has exited.
+
+use serde_json::{json, Value};
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+fn frame(message: Value) -> String {
+    let body = message.to_string();
+    format!("Content-Length: {}\r\n\r\n{}", body.len(), body)
+}
+
+fn messages(output: &str) -> Vec<Value> {
+    output
+        .split("Content-Length: ")
+        .filter(|part| !part.is_empty())
+        .map(|part| {
+            let (_, body) = part.split_once("\r\n\r\n").unwrap();
+            serde_json::from_str(body).unwrap()
+        })
+        .collect()
+}
+
+fn response(messages: &[Value], id: u64) -> &Value {
+    let message = messages.iter().find(|m| m["id"] == id).unwrap();
+    &message["result"]
+}
+
+#[test]
+fn test_session() {
+    let uri = "file:///tmp/program.bfc";
+    let text =
+        "carry ( x )\nx = 1\nwhile x != 0 {\n  move_right\n  x = 0\n  move_left\n}\noutput ( y )";
+    let position = |line: u32, character: u32| {
+        json!({
+            "textDocument": {"uri": uri},
+            "position": {"line": line, "character": character}
+        })
+    };
+    let mut references = position(4, 2);
+    references["context"] = json!({"includeDeclaration": true});
+    let requests = [
+        json!({"jsonrpc": "2.0", "id": 1, "method": "initialize", "params": {"capabilities": {}}}),
+        json!({"jsonrpc": "2.0", "method": "initialized", "params": {}}),
+        json!({"jsonrpc": "2.0", "method": "textDocument/didOpen", "params": {
+            "textDocument": {"uri": uri, "languageId": "bfc", "version": 1, "text": text}
+        }}),
+        json!({"jsonrpc": "2.0", "id": 2, "method": "textDocument/hover", "params": position(2, 6)}),
+        json!({"jsonrpc": "2.0", "id": 3, "method": "textDocument/definition", "params": position(4, 2)}),
+        json!({"jsonrpc": "2.0", "id": 4, "method": "textDocument/references", "params": references}),
+        json!({"jsonrpc": "2.0", "id": 5, "method": "textDocument/formatting", "params": {
+            "textDocument": {"uri": uri}, "options": {"tabSize": 2, "insertSpaces": true}
+        }}),
+        json!({"jsonrpc": "2.0", "id": 6, "method": "shutdown"}),
+        json!({"jsonrpc": "2.0", "method": "exit"}),
+    ];
+    let mut child = Command::new(env!("CARGO_BIN_EXE_bfc-lsp"))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let input = requests.into_iter().map(frame).collect::<String>();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(input.as_bytes())
+        .unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success());
+    let messages = messages(&String::from_utf8(output.stdout).unwrap());
+
+    let diagnostics = messages
+        .iter()
+        .find(|m| m["method"] == "textDocument/publishDiagnostics")
+        .unwrap();
+    let diagnostics = diagnostics["params"]["diagnostics"].as_array().unwrap();
+    assert_eq!(diagnostics.len(), 1);
+    assert_eq!(
+        diagnostics[0]["range"],
+        json!({"start": {"line": 7, "character": 9}, "end": {"line": 7, "character": 10}})
+    );
+
+    let hover = &response(&messages, 2)["contents"]["value"];
+    assert!(hover.as_str().unwrap().starts_with("`x`: cell "));
+    assert_eq!(
+        response(&messages, 3)["range"]["start"],
+        json!({"line": 0, "character": 8})
+    );
+    assert_eq!(response(&messages, 4).as_array().unwrap().len(), 4);
+    let edit = &response(&messages, 5)[0]["newText"];
+    assert!(edit.as_str().unwrap().ends_with("}\noutput ( y )\n"));
+}
@@ -0,0 +1,64 @@
+// Golden tests: every `programs/*.bfc` is compiled and run on the built-in
+// interpreter with `<name>.in` as stdin (nothing if missing), and its stdout
+// has to match `<name>.out`.
+
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+fn run(program: &Path) -> Result<(), String> {
+    let expect = fs::read(program.with_extension("out"))
+        .map_err(|err| format!("cannot read the expected output: {}", err))?;
+    let input = fs::read(program.with_extension("in")).unwrap_or_default();
+    let mut child = Command::new(env!("CARGO_BIN_EXE_bf-compiler"))
+        .arg("run")
+        .arg(program)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|err| err.to_string())?;
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(&input)
+        .map_err(|err| err.to_string())?;
+    let output = child.wait_with_output().map_err(|err| err.to_string())?;
+    if !output.status.success() {
+        return Err(format!(
+            "failed: {}",
+            String::from_utf8_lossy(&output.stderr)
+        ));
+    }
+    if output.stdout != expect {
+        return Err(format!(
+            "expected {:?}, found {:?}",
+            String::from_utf8_lossy(&expect),
+            String::from_utf8_lossy(&output.stdout)
+        ));
+    }
+    Ok(())
+}
+
+#[test]
+fn test_programs() {
+    let directory = Path::new(env!("CARGO_MANIFEST_DIR")).join("programs");
+    let mut programs = fs::read_dir(directory)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "bfc"))
+        .collect::<Vec<_>>();
+    programs.sort();
+    assert!(!programs.is_empty());
+    let failures = programs
+        .iter()
+        .filter_map(|program| {
+            run(program)
+                .err()
+                .map(|err| format!("{}: {}", program.display(), err))
+        })
+        .collect::<Vec<_>>();
+    assert!(failures.is_empty(), "\n{}", failures.join("\n"));
+}